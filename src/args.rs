@@ -1,56 +1,22 @@
 //! The arguments for the jail.
 
-use clap::{ArgEnum, ArgGroup, Parser};
-
-/// The languages supported by the jail.
-#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ArgEnum)]
-pub enum Language {
-    C,
-    #[clap(name = "c11-gcc")]
-    C11GCC,
-    C11Clang,
-    Cpp,
-    Cpp03GCC,
-    Cpp03Clang,
-    Cpp11,
-    Cpp11GCC,
-    Cpp11Clang,
-    Cpp17GCC,
-    Cpp17Clang,
-    Cpp20GCC,
-    Cpp20Clang,
-    #[clap(name = "pas")]
-    Pascal,
-    Lua,
-    #[clap(name = "hs")]
-    Haskell,
-    #[clap(name = "java")]
-    Java,
-    #[clap(name = "kt")]
-    Kotlin,
-    #[clap(name = "py")]
-    Python,
-    #[clap(name = "py2")]
-    Python2,
-    #[clap(name = "py3")]
-    Python3,
-    #[clap(name = "rb")]
-    Ruby,
-    #[clap(name = "cs")]
-    CSharp,
-    #[clap(name = "rs")]
-    Rust,
-    Go,
-    #[clap(name = "js")]
-    JavaScript,
-    #[clap(name = "kj")]
-    KarelJava,
-    #[clap(name = "kp")]
-    KarelPascal,
+use clap::{ArgEnum, ArgGroup, Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+
+/// The format of the .meta file.
+#[derive(ArgEnum, Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MetaFormat {
+    /// The legacy format, with one `key:value` pair per line.
+    Text,
+    /// A JSON object, described by `schemas/meta.schema.json`.
+    Json,
 }
 
+/// The default size of the tmpfs mounted at /tmp.
+const DEFAULT_TMP_SIZE: u64 = 64 * 1024 * 1024;
+
 /// [`clap`](::clap) arguments for the sandboxing.
-#[derive(Parser, Clone, Debug)]
+#[derive(Parser, Serialize, Deserialize, Clone, Debug)]
 #[clap(author, version, about, long_about = None, trailing_var_arg(true))]
 #[clap(group(ArgGroup::new("run_mode").required(true).args(&["compile", "run"])))]
 pub struct Args {
@@ -58,15 +24,15 @@ pub struct Args {
     #[clap(long, default_value = ".")]
     pub root: String,
 
-    /// Run omegajail in compilation mode for the specified language
+    /// Run omegajail in compilation mode for the specified language, as defined in the language
+    /// registry
     #[clap(
         long,
-        arg_enum,
         value_name = "LANGUAGE",
         requires = "compile-source",
         requires = "compile-target"
     )]
-    pub compile: Option<Language>,
+    pub compile: Option<String>,
 
     /// Add the file to the compilation
     #[clap(long, value_name = "PATH")]
@@ -76,9 +42,9 @@ pub struct Args {
     #[clap(long, value_name = "PATH", default_value = "Main")]
     pub compile_target: String,
 
-    /// Run omegajail in run mode for the specified language
-    #[clap(long, arg_enum, value_name = "LANGUAGE", requires = "run-target")]
-    pub run: Option<Language>,
+    /// Run omegajail in run mode for the specified language, as defined in the language registry
+    #[clap(long, value_name = "LANGUAGE", requires = "run-target")]
+    pub run: Option<String>,
 
     /// Set the target name to execute
     #[clap(long, value_name = "PATH", default_value = "Main")]
@@ -92,6 +58,43 @@ pub struct Args {
     #[clap(long)]
     pub homedir_writable: bool,
 
+    /// Specifies that /home will be writable through an overlay on top of --homedir, backed by a
+    /// tmpfs that is discarded when the sandbox exits
+    #[clap(long, conflicts_with = "homedir-writable")]
+    pub homedir_overlay: bool,
+
+    /// Sets the maximum size of the files written to the /home overlay
+    #[clap(long, value_name = "BYTES", default_value = "67108864")]
+    pub homedir_overlay_size: u64,
+
+    /// Sets the maximum number of files and directories written to the /home overlay
+    #[clap(long, value_name = "COUNT", default_value = "1024")]
+    pub homedir_overlay_inodes: u64,
+
+    /// Sets the size of the tmpfs mounted at /tmp. 0 leaves /tmp read-only
+    #[clap(long, value_name = "BYTES", default_value_t = DEFAULT_TMP_SIZE)]
+    pub tmp_size: u64,
+
+    /// Sets the maximum number of files and directories in /tmp
+    #[clap(long, value_name = "COUNT")]
+    pub tmp_inodes: Option<u64>,
+
+    /// Allows executing files in /tmp
+    #[clap(long)]
+    pub tmp_exec: bool,
+
+    /// Additional tmpfs mounts, with an optional `:exec` suffix to allow executing files in them
+    #[clap(long, value_name = "PATH:BYTES[:exec]")]
+    pub tmpfs: Vec<String>,
+
+    /// Copies the file at |PATH| (relative to /home) to |DEST| once the program exits
+    #[clap(long, value_name = "PATH:DEST")]
+    pub extract: Vec<String>,
+
+    /// Sets the maximum size of each file copied by --extract
+    #[clap(long, value_name = "BYTES", default_value = "67108864")]
+    pub extract_size_limit: u64,
+
     /// Redirects stdin
     #[clap(long, short = '0', value_name = "PATH")]
     pub stdin: Option<String>,
@@ -108,6 +111,10 @@ pub struct Args {
     #[clap(long, short = 'M', value_name = "PATH")]
     pub meta: Option<String>,
 
+    /// The format of the .meta file
+    #[clap(long, arg_enum, value_name = "FORMAT", default_value = "text")]
+    pub meta_format: MetaFormat,
+
     /// Sets the time limit
     #[clap(long, short = 't', value_name = "MSEC")]
     pub time_limit: Option<u64>,
@@ -124,6 +131,20 @@ pub struct Args {
     #[clap(long, short = 'm', value_name = "BYTES")]
     pub memory_limit: Option<u64>,
 
+    /// Enforces the memory limit through the memory cgroup for every language, instead of only
+    /// for the ones that opt into it. RLIMIT_AS is used instead if the cgroup is not available
+    #[clap(long)]
+    pub cgroup_memory_limit: bool,
+
+    /// Sets the maximum number of processes and threads that can exist in the sandbox at once
+    #[clap(long, value_name = "COUNT")]
+    pub pids_limit: Option<u64>,
+
+    /// Pins the sandbox to this CPU core, instead of the first one that omegajail is allowed to run
+    /// on
+    #[clap(long, value_name = "CPU")]
+    pub cpu: Option<usize>,
+
     /// The cgroup hierarchy in which processes will be placed
     #[clap(
         long,
@@ -137,15 +158,310 @@ pub struct Args {
     #[clap(long)]
     pub disable_sandboxing: bool,
 
-    /// Additional bind-mounts
-    #[clap(long, value_name = "SOURCE:TARGET")]
+    /// Additional mounts. Either SOURCE:TARGET for a read-only bind-mount, or a comma-separated
+    /// list of options, such as type=bind,src=SOURCE,dst=TARGET,rw,noexec or
+    /// type=tmpfs,dst=TARGET,size=BYTES
+    #[clap(long, value_name = "SPEC")]
     pub bind: Vec<String>,
 
+    /// A .policy file to use instead of the seccomp profile of the language. It is compiled when
+    /// the sandbox starts, and the result is cached in the policies/cache directory in --root
+    #[clap(long, value_name = "PATH")]
+    pub seccomp_policy: Option<String>,
+
+    /// Records every syscall that the program makes instead of enforcing the seccomp profile, and
+    /// writes a draft policy to |PREFIX|.policy and the syscall counts to |PREFIX|.frequency once
+    /// it exits
+    #[clap(
+        long,
+        value_name = "PREFIX",
+        conflicts_with_all = &["allow-sigsys-fallback", "batch", "disable-sandboxing"]
+    )]
+    pub audit_syscalls: Option<String>,
+
     /// Allows downgrading to the SIGSYS-based seccomp filter that doesn't provide correct SYSACLL
     /// information always
     #[clap(long)]
     pub allow_sigsys_fallback: bool,
 
+    /// Run the program in --run once for each test case in the manifest at |path|, which has one
+    /// `[[case]]` table with optional stdin, stdout, stderr, and meta paths per test case
+    #[clap(
+        long,
+        value_name = "PATH",
+        requires = "run",
+        conflicts_with_all = &["compile", "stdin", "stdout", "stderr", "meta", "interactor"]
+    )]
+    pub batch: Option<String>,
+
+    /// Run omegajail in interactive mode: the program in --run is run alongside an interactor
+    /// for the specified language, with their stdin and stdout connected to each other
+    #[clap(
+        long,
+        value_name = "LANGUAGE",
+        requires = "run",
+        conflicts_with_all = &["compile", "stdin", "stdout"]
+    )]
+    pub interactor: Option<String>,
+
+    /// Set the target name of the interactor to execute
+    #[clap(long, value_name = "PATH", default_value = "Main")]
+    pub interactor_target: String,
+
+    /// Specifies |path| to be mounted as /home in the interactor's sandbox. Defaults to --homedir
+    #[clap(long, value_name = "PATH")]
+    pub interactor_homedir: Option<String>,
+
+    /// Writes a .meta file for the interactor
+    #[clap(long, value_name = "PATH")]
+    pub interactor_meta: Option<String>,
+
+    /// Redirects the interactor's stderr
+    #[clap(long, value_name = "PATH")]
+    pub interactor_stderr: Option<String>,
+
+    /// Sets the time limit of the interactor
+    #[clap(long, value_name = "MSEC")]
+    pub interactor_time_limit: Option<u64>,
+
+    /// Sets the memory limit of the interactor
+    #[clap(long, value_name = "BYTES")]
+    pub interactor_memory_limit: Option<u64>,
+
+    /// Additional arguments to the interactor
+    #[clap(long, value_name = "ARG")]
+    pub interactor_arg: Vec<String>,
+
     /// Any additional arguments to the executable
     pub extra_args: Vec<String>,
 }
+
+/// [`clap`](::clap) subcommands that run omegajail in a mode other than sandboxing a single
+/// program.
+#[derive(Subcommand, Clone, Debug)]
+pub enum Subcommands {
+    /// Serve requests to run sandboxed programs over a Unix socket
+    Serve {
+        /// The path of the Unix socket to listen on
+        #[clap(long, value_name = "PATH")]
+        socket: String,
+
+        /// The permissions of the Unix socket, in octal. Anyone who can connect to it can run
+        /// programs in the sandbox, and mount any directory under --homedir-root as their home
+        /// directory, writable if they ask for it
+        #[clap(long, value_name = "MODE", default_value = "600", parse(try_from_str = parse_mode))]
+        socket_mode: u32,
+
+        /// Root of the omegajail runtime
+        #[clap(long, default_value = ".")]
+        root: String,
+
+        /// The directory that contains the home directories that clients can choose. The --homedir
+        /// of every request is relative to it, and cannot be outside of it
+        #[clap(long, value_name = "PATH")]
+        homedir_root: String,
+
+        /// The cgroup hierarchy in which processes will be placed
+        #[clap(
+            long,
+            default_value = "/system.slice/omegaup-runner.service/omegajail",
+            value_name = "PATH"
+        )]
+        cgroup_path: String,
+
+        /// Additional mounts for every sandbox, in the same format as in --bind
+        #[clap(long, value_name = "SPEC")]
+        bind: Vec<String>,
+
+        /// Allows downgrading every sandbox to the SIGSYS-based seccomp filter if seccomp user
+        /// notifications are not available
+        #[clap(long)]
+        allow_sigsys_fallback: bool,
+    },
+
+    /// Removes the cgroups left behind by omegajail processes that are no longer running
+    GcCgroups {
+        /// The cgroup hierarchy in which processes are placed
+        #[clap(
+            long,
+            default_value = "/system.slice/omegaup-runner.service/omegajail",
+            value_name = "PATH"
+        )]
+        cgroup_path: String,
+
+        /// Removes the cgroups whose owner cannot be checked, because they were created in another
+        /// pid namespace or by an older version, once they are older than this
+        #[clap(long, value_name = "SEC", default_value = "86400")]
+        max_age: u64,
+    },
+
+    /// Works with seccomp policies
+    Policy {
+        #[clap(subcommand)]
+        command: PolicyCommand,
+    },
+}
+
+/// [`clap`](::clap) subcommands of `omegajail policy`.
+#[derive(Subcommand, Clone, Debug)]
+pub enum PolicyCommand {
+    /// Compiles a .policy file into the BPF program that is loaded as the seccomp filter
+    Compile {
+        /// The action for syscalls that the policy does not allow, such as user-notify. Defaults
+        /// to the policy's @default action, or kill-process
+        #[clap(long, value_name = "ACTION")]
+        default_action: Option<String>,
+
+        /// The .policy file
+        policy: String,
+
+        /// The path where the BPF program is written
+        output: String,
+    },
+
+    /// Reports problems in .policy files, such as unknown syscalls or rules that are never used
+    Check {
+        /// The .policy files, or directories whose .policy files are checked
+        #[clap(required = true)]
+        paths: Vec<String>,
+    },
+
+    /// Disassembles a compiled BPF program, or shows what it returns for a syscall
+    Dump {
+        /// The BPF program, such as the one in seccomp_bpf_filter_notify_contents
+        program: String,
+
+        /// Instead of disassembling the program, shows what it returns for this syscall, given
+        /// by name or number
+        #[clap(long, value_name = "SYSCALL")]
+        syscall: Option<String>,
+
+        /// An argument to --syscall, such as 3, 0x80000, or O_RDONLY|O_CLOEXEC. Missing
+        /// arguments are zero
+        #[clap(
+            long,
+            value_name = "VALUE",
+            requires = "syscall",
+            allow_hyphen_values = true
+        )]
+        arg: Vec<String>,
+    },
+}
+
+impl Args {
+    /// Returns the arguments for the interactor's sandbox if running in interactive mode. Only the
+    /// runtime, the cgroup path, the sandboxing settings and the wall time limit are shared with
+    /// the contestant. Everything else comes from the `--interactor-*` flags or is left with its
+    /// default value, so that none of the contestant's limits or mounts apply to the interactor.
+    pub fn interactor_args(&self) -> Option<Args> {
+        let interactor = self.interactor.as_ref()?;
+        Some(Args {
+            root: self.root.clone(),
+            compile: None,
+            compile_source: None,
+            compile_target: self.compile_target.clone(),
+            run: Some(interactor.clone()),
+            run_target: self.interactor_target.clone(),
+            homedir: self
+                .interactor_homedir
+                .clone()
+                .unwrap_or_else(|| self.homedir.clone()),
+            homedir_writable: false,
+            homedir_overlay: false,
+            // Unused without --homedir-overlay.
+            homedir_overlay_size: 0,
+            homedir_overlay_inodes: 0,
+            tmp_size: DEFAULT_TMP_SIZE,
+            tmp_inodes: None,
+            tmp_exec: false,
+            tmpfs: vec![],
+            extract: vec![],
+            extract_size_limit: 0,
+            // Connected to the contestant through pipes.
+            stdin: None,
+            stdout: None,
+            stderr: self.interactor_stderr.clone(),
+            meta: self.interactor_meta.clone(),
+            meta_format: self.meta_format,
+            time_limit: self.interactor_time_limit,
+            extra_wall_time_limit: self.extra_wall_time_limit,
+            output_limit: None,
+            memory_limit: self.interactor_memory_limit,
+            cgroup_memory_limit: false,
+            pids_limit: None,
+            cpu: None,
+            cgroup_path: self.cgroup_path.clone(),
+            disable_sandboxing: self.disable_sandboxing,
+            bind: vec![],
+            seccomp_policy: None,
+            audit_syscalls: None,
+            allow_sigsys_fallback: self.allow_sigsys_fallback,
+            batch: None,
+            interactor: None,
+            interactor_target: String::new(),
+            interactor_homedir: None,
+            interactor_meta: None,
+            interactor_stderr: None,
+            interactor_time_limit: None,
+            interactor_memory_limit: None,
+            interactor_arg: vec![],
+            extra_args: self.interactor_arg.clone(),
+        })
+    }
+}
+
+/// Parses a file mode in octal, such as `660`.
+fn parse_mode(mode: &str) -> Result<u32, String> {
+    match u32::from_str_radix(mode, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => Err(format!("invalid file mode {:?}", mode)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use clap::Parser;
+
+    use crate::args::Args;
+
+    #[test]
+    fn test_interactor_args() -> Result<()> {
+        let args = Args::try_parse_from([
+            "omegajail",
+            "--homedir",
+            "/home",
+            "--run",
+            "cpp17-gcc",
+            "--interactor",
+            "py3",
+            "--interactor-time-limit",
+            "2000",
+            "--output-limit",
+            "1024",
+            "--bind",
+            "/srv:/srv",
+            "--cpu",
+            "3",
+            "--pids-limit",
+            "16",
+            "--extract",
+            "output.txt:/tmp/output.txt",
+            "--tmp-size",
+            "1024",
+        ])?;
+        let interactor_args = args.interactor_args().unwrap();
+        assert_eq!(interactor_args.run.as_deref(), Some("py3"));
+        assert_eq!(interactor_args.homedir, "/home");
+        assert_eq!(interactor_args.time_limit, Some(2000));
+        assert_eq!(interactor_args.output_limit, None);
+        assert!(interactor_args.bind.is_empty());
+        assert_eq!(interactor_args.cpu, None);
+        assert_eq!(interactor_args.pids_limit, None);
+        assert!(interactor_args.extract.is_empty());
+        assert_eq!(interactor_args.tmp_size, 64 * 1024 * 1024);
+        assert_eq!(interactor_args.interactor, None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,284 @@
+//! Recording of the syscalls made by the sandboxed process, to help write policies.
+//!
+//! In audit mode, the seccomp filter sends a notification for every syscall instead of only for
+//! the forbidden ones. The sandboxed init records each one and lets the kernel run it, and once the
+//! sandboxed process exits, sends the record to the parent process, which writes it as a draft
+//! policy and a frequency file in the format of the ones in `policies/`.
+
+use std::fmt::Write as _;
+use std::fs::write;
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::seccomp::{
+    bpf, compile, Action, Arch, ArgComparison, Filter, FilterStatement, Location, Operator,
+    ParsedPolicy,
+};
+use crate::sys::SeccompData;
+
+/// The arguments whose values are recorded, since policies usually only allow some of them.
+const RECORDED_ARGS: &[(&str, usize)] = &[
+    ("arch_prctl", 0),
+    ("clone", 0),
+    ("fcntl", 1),
+    ("fcntl64", 1),
+    ("futex", 1),
+    ("ioctl", 1),
+    ("madvise", 2),
+    ("mmap", 2),
+    ("mmap2", 2),
+    ("mprotect", 2),
+    ("personality", 0),
+    ("prctl", 0),
+    ("prlimit64", 1),
+    ("socket", 0),
+];
+
+/// The maximum number of distinct values that are recorded for an argument. If the sandboxed
+/// process uses more than these, any value is allowed in the draft policy.
+const MAX_ARG_VALUES: usize = 16;
+
+/// A syscall made by the sandboxed process.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct AuditedSyscall {
+    pub nr: u32,
+    pub count: u64,
+    /// The index of the argument whose values were recorded, if any.
+    pub arg: Option<usize>,
+    /// The distinct values of `arg`, in increasing order, or `None` if there were too many.
+    pub arg_values: Option<Vec<u64>>,
+}
+
+/// Every syscall made by the sandboxed process, ordered by number.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub(crate) struct SyscallAudit {
+    pub syscalls: Vec<AuditedSyscall>,
+}
+
+impl SyscallAudit {
+    /// Records a syscall from a seccomp notification.
+    pub(crate) fn record(&mut self, data: &SeccompData) {
+        let nr = data.nr as u32;
+        let index = match self
+            .syscalls
+            .binary_search_by_key(&nr, |syscall| syscall.nr)
+        {
+            Ok(index) => index,
+            Err(index) => {
+                let arg = Arch::native().syscall_name(nr).and_then(|name| {
+                    RECORDED_ARGS
+                        .iter()
+                        .find(|(syscall, _)| *syscall == name)
+                        .map(|(_, arg)| *arg)
+                });
+                self.syscalls.insert(
+                    index,
+                    AuditedSyscall {
+                        nr,
+                        count: 0,
+                        arg,
+                        arg_values: Some(vec![]),
+                    },
+                );
+                index
+            }
+        };
+        let syscall = &mut self.syscalls[index];
+        syscall.count += 1;
+        if let (Some(arg), Some(values)) = (syscall.arg, &mut syscall.arg_values) {
+            let value = data.args[arg] & Arch::native().arg_mask();
+            if let Err(position) = values.binary_search(&value) {
+                if values.len() == MAX_ARG_VALUES {
+                    syscall.arg_values = None;
+                } else {
+                    values.insert(position, value);
+                }
+            }
+        }
+    }
+
+    /// Returns a draft policy that allows every recorded syscall, only with the recorded values of
+    /// its arguments, and that takes the frequencies from `frequency_file_name`.
+    pub(crate) fn policy(&self, frequency_file_name: &str) -> String {
+        let arch = Arch::native();
+        let mut lines = Vec::new();
+        for syscall in &self.syscalls {
+            let name = match arch.syscall_name(syscall.nr) {
+                Some(name) => name,
+                None => continue,
+            };
+            let rule = match (syscall.arg, &syscall.arg_values) {
+                (Some(arg), Some(values)) if !values.is_empty() => values
+                    .iter()
+                    .map(|value| format!("arg{} == {:#x}", arg, value))
+                    .collect::<Vec<_>>()
+                    .join(" || "),
+                _ => "allow".to_string(),
+            };
+            lines.push(format!("{}: {}", name, rule));
+        }
+        lines.sort();
+
+        let mut policy = format!(
+            "# Recorded by omegajail --audit-syscalls. Review it before use: the program may need\n\
+             # other syscalls or argument values when given other inputs.\n\
+             @frequency ./{}\n\n",
+            frequency_file_name
+        );
+        for line in lines {
+            writeln!(policy, "{}", line).unwrap();
+        }
+        for syscall in &self.syscalls {
+            if arch.syscall_name(syscall.nr).is_none() {
+                writeln!(policy, "# unknown syscall {}", syscall.nr).unwrap();
+            }
+        }
+        policy
+    }
+
+    /// Returns the number of times each syscall was made, in the format of `@frequency` files.
+    pub(crate) fn frequency(&self) -> String {
+        let arch = Arch::native();
+        let mut lines: Vec<String> = self
+            .syscalls
+            .iter()
+            .filter_map(|syscall| {
+                arch.syscall_name(syscall.nr)
+                    .map(|name| format!("{}: {}\n", name, syscall.count))
+            })
+            .collect();
+        lines.sort();
+        lines.concat()
+    }
+
+    /// Writes the draft policy to `prefix.policy` and the frequencies to `prefix.frequency`.
+    pub(crate) fn write(&self, prefix: &Path) -> Result<()> {
+        let file_name = prefix
+            .file_name()
+            .ok_or_else(|| anyhow!("{:?} does not have a file name", prefix))?
+            .to_string_lossy();
+        let policy_path = prefix.with_file_name(format!("{}.policy", file_name));
+        let frequency_path = prefix.with_file_name(format!("{}.frequency", file_name));
+        write(
+            &policy_path,
+            self.policy(&format!("{}.frequency", file_name)),
+        )
+        .with_context(|| anyhow!("write {:?}", policy_path))?;
+        write(&frequency_path, self.frequency())
+            .with_context(|| anyhow!("write {:?}", frequency_path))?;
+        Ok(())
+    }
+}
+
+/// Returns the filter that is used in audit mode, which sends a notification for every syscall.
+///
+/// The exception are the writes to `sock_fd` that send the notification fd to the sandboxed init
+/// right after the filter is installed, since nothing would be able to handle them otherwise.
+pub(crate) fn audit_filter(sock_fd: RawFd) -> Result<Vec<u8>> {
+    let arch = Arch::native();
+    let mut filter_statements = Vec::new();
+    for syscall in ["write", "sendto", "sendmsg"] {
+        filter_statements.push(FilterStatement {
+            syscall: syscall.to_string(),
+            nr: arch
+                .syscall(syscall)
+                .ok_or_else(|| anyhow!("nonexistent syscall {}", syscall))?,
+            frequency: 1,
+            filters: vec![Filter {
+                expression: Some(vec![vec![ArgComparison {
+                    arg: 0,
+                    op: Operator::Eq,
+                    value: sock_fd as u64,
+                }]]),
+                action: Action::Allow,
+                location: Location {
+                    path: PathBuf::from("audit"),
+                    line: 0,
+                },
+            }],
+        });
+    }
+    let policy = ParsedPolicy {
+        default_action: None,
+        filter_statements,
+        frequencies: vec![],
+        files: vec![],
+    };
+    Ok(bpf::to_bytes(&compile(
+        &arch,
+        &policy,
+        Some(Action::UserNotify),
+    )?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+
+    use anyhow::Result;
+    use tempdir::TempDir;
+
+    use crate::jail::audit::SyscallAudit;
+    use crate::seccomp::{check_file, Arch, PolicyParser};
+    use crate::sys::SeccompData;
+
+    #[test]
+    fn test_syscall_audit() -> Result<()> {
+        let arch = Arch::native();
+        let syscall = |name: &str, args: [u64; 6]| SeccompData {
+            nr: arch.syscall(name).unwrap() as i32,
+            arch: arch.audit_arch,
+            instruction_pointer: 0,
+            args,
+        };
+        let mut audit = SyscallAudit::default();
+        for _ in 0..3 {
+            audit.record(&syscall("read", [0, 0, 0, 0, 0, 0]));
+        }
+        audit.record(&syscall("ioctl", [1, libc::TIOCGWINSZ, 0, 0, 0, 0]));
+        audit.record(&syscall("ioctl", [0, libc::TCGETS, 0, 0, 0, 0]));
+        audit.record(&syscall("ioctl", [1, libc::TCGETS, 0, 0, 0, 0]));
+        for prot in 0..20 {
+            audit.record(&syscall("mprotect", [0, 4096, prot, 0, 0, 0]));
+        }
+        audit.record(&syscall("exit_group", [0, 0, 0, 0, 0, 0]));
+
+        assert_eq!(
+            audit.frequency(),
+            "exit_group: 1\nioctl: 3\nmprotect: 20\nread: 3\n"
+        );
+
+        let dir = TempDir::new("audit")?;
+        audit.write(&dir.path().join("test"))?;
+        assert_eq!(
+            read_to_string(dir.path().join("test.frequency"))?,
+            audit.frequency()
+        );
+        let policy = read_to_string(dir.path().join("test.policy"))?;
+        assert!(
+            policy.contains("@frequency ./test.frequency\n"),
+            "{}",
+            policy
+        );
+        assert!(
+            policy.contains(&format!(
+                "\nioctl: arg1 == {:#x} || arg1 == {:#x}\n",
+                libc::TCGETS.min(libc::TIOCGWINSZ),
+                libc::TCGETS.max(libc::TIOCGWINSZ)
+            )),
+            "{}",
+            policy
+        );
+        assert!(policy.contains("\nmprotect: allow\n"), "{}", policy);
+
+        // The draft is a valid policy without any problems.
+        assert_eq!(check_file(&dir.path().join("test.policy"))?, vec![]);
+        let parsed = PolicyParser::new(arch).parse_file(&dir.path().join("test.policy"))?;
+        assert_eq!(parsed.filter_statements.len(), 4);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+//! Support for running the same target against many test cases in a single invocation.
+
+use std::collections::VecDeque;
+use std::fs::read_to_string;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+
+use crate::args;
+use crate::jail::{options, CpuPool, Jail, JailResult};
+
+/// The stdio redirections and .meta file of a single test case in a batch.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct BatchCase {
+    /// Redirects stdin.
+    pub stdin: Option<String>,
+    /// Redirects stdout.
+    pub stdout: Option<String>,
+    /// Redirects stderr.
+    pub stderr: Option<String>,
+    /// Writes a .meta file.
+    pub meta: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct BatchManifest {
+    #[serde(default, rename = "case")]
+    cases: Vec<BatchCase>,
+}
+
+impl BatchCase {
+    /// Reads the list of test cases from a TOML manifest with one `[[case]]` table per test case.
+    pub fn load_manifest<P: AsRef<Path>>(path: P) -> Result<Vec<BatchCase>> {
+        let path = path.as_ref();
+        let contents = read_to_string(path).with_context(|| anyhow!("read {:?}", path))?;
+        let manifest: BatchManifest =
+            toml::from_str(&contents).with_context(|| anyhow!("parse {:?}", path))?;
+        Ok(manifest.cases)
+    }
+}
+
+/// A builder for running a batch of test cases, each one in its own [`Jail`].
+///
+/// The options (language recipe, seccomp-bpf filters, limits) are computed only once and reused
+/// for every case, so only the stdio redirections change between them.
+pub struct BatchCommand {
+    args: args::Args,
+    cases: Vec<BatchCase>,
+    cpu_pool: Option<CpuPool>,
+}
+
+impl BatchCommand {
+    /// Constructs a new `BatchCommand` that runs the program described by `args` against each one
+    /// of `cases`.
+    pub fn new(args: args::Args, cases: Vec<BatchCase>) -> BatchCommand {
+        BatchCommand {
+            args,
+            cases,
+            cpu_pool: None,
+        }
+    }
+
+    /// Runs as many cases concurrently as there are cores in `pool`, each one pinned to its own
+    /// core, instead of one after the other.
+    pub fn cpu_pool(mut self, pool: CpuPool) -> BatchCommand {
+        self.cpu_pool = Some(pool);
+        self
+    }
+
+    /// Runs every case, returning their results in the same order as the cases.
+    pub fn run(self) -> Result<Vec<JailResult>> {
+        let jail_options = options::JailOptions::new(self.args).context("create jail options")?;
+        let mut results = Vec::with_capacity(self.cases.len());
+        // All the jails are spawned from this thread. Cases are started while there are free cores,
+        // and otherwise the oldest running one is waited for, which returns its core to the pool.
+        let mut running: VecDeque<(usize, Jail)> = VecDeque::new();
+        for (i, case) in self.cases.iter().enumerate() {
+            let cpu_lease = match &self.cpu_pool {
+                Some(pool) => loop {
+                    if let Some(cpu_lease) = pool.try_acquire() {
+                        break Some(cpu_lease);
+                    }
+                    match running.pop_front() {
+                        Some((j, jail)) => {
+                            results.push(jail.wait().with_context(|| anyhow!("wait case {}", j))?)
+                        }
+                        None => break Some(pool.acquire()),
+                    }
+                },
+                None => None,
+            };
+            let mut case_options = jail_options.clone();
+            case_options
+                .set_stdio(
+                    case.stdin.as_deref(),
+                    case.stdout.as_deref(),
+                    case.stderr.as_deref(),
+                    case.meta.as_deref(),
+                )
+                .with_context(|| anyhow!("set up case {}", i))?;
+            if let Some(cpu_lease) = &cpu_lease {
+                case_options.cpu = Some(cpu_lease.cpu());
+            }
+            let mut jail = Jail::new(case_options).with_context(|| anyhow!("spawn case {}", i))?;
+            jail.cpu_lease = cpu_lease;
+            if self.cpu_pool.is_none() {
+                results.push(jail.wait().with_context(|| anyhow!("wait case {}", i))?);
+            } else {
+                running.push_back((i, jail));
+            }
+        }
+        for (i, jail) in running {
+            results.push(jail.wait().with_context(|| anyhow!("wait case {}", i))?);
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+
+    use anyhow::Result;
+    use tempdir::TempDir;
+
+    use crate::jail::BatchCase;
+
+    #[test]
+    fn test_load_manifest() -> Result<()> {
+        let tmp_dir = TempDir::new("manifest")?;
+        let path = tmp_dir.path().join("batch.toml");
+        write(
+            &path,
+            r#"
+[[case]]
+stdin = "1.in"
+stdout = "1.out"
+meta = "1.meta"
+
+[[case]]
+stdin = "2.in"
+stderr = "2.err"
+"#,
+        )?;
+        let cases = BatchCase::load_manifest(&path)?;
+        assert_eq!(cases.len(), 2);
+        assert_eq!(cases[0].stdin.as_deref(), Some("1.in"));
+        assert_eq!(cases[0].stdout.as_deref(), Some("1.out"));
+        assert_eq!(cases[0].stderr, None);
+        assert_eq!(cases[0].meta.as_deref(), Some("1.meta"));
+        assert_eq!(cases[1].stderr.as_deref(), Some("2.err"));
+
+        write(&path, "[[case]]\nstdn = \"1.in\"\n")?;
+        assert!(BatchCase::load_manifest(&path).is_err());
+
+        Ok(())
+    }
+}
@@ -1,13 +1,20 @@
 use std::fmt::Debug;
-use std::fs::{create_dir, remove_dir, write};
-use std::io::ErrorKind;
+use std::fs::{
+    create_dir, metadata, read_dir, read_to_string, remove_dir, write, File, OpenOptions,
+};
+use std::io::{ErrorKind, Write};
 use std::ops::Drop;
+use std::os::unix::fs::{FileExt, MetadataExt};
 use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::{Duration, SystemTime};
 
 use anyhow::{anyhow, bail, Context, Result};
 use rand::{thread_rng, Rng};
 
-use nix::unistd::Pid;
+use nix::errno::Errno;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::{getpid, Pid};
 
 pub(crate) struct CGroup {
     path: PathBuf,
@@ -20,16 +27,7 @@ impl CGroup {
         P1: 'a + Debug + AsRef<Path>,
         P2: 'a + Debug + AsRef<Path>,
     {
-        let root = PathBuf::from("/sys/fs/cgroup").join(&subsystem).join(
-            if cgroup_path.as_ref().is_absolute() {
-                cgroup_path
-                    .as_ref()
-                    .strip_prefix("/")
-                    .with_context(|| anyhow!("relativize {:?}", &cgroup_path))?
-            } else {
-                cgroup_path.as_ref()
-            },
-        );
+        let root = cgroup_root(&subsystem, &cgroup_path)?;
         let v2 = subsystem.as_ref() == Path::new("");
         if !root.exists() {
             create_dir(&root).with_context(|| anyhow!("create_dir({:?})", &root))?;
@@ -39,16 +37,36 @@ impl CGroup {
                     .with_context(|| anyhow!("write +memory to {:?}", &subtree_control))?;
             }
         }
+        if v2 {
+            // The pids controller is optional, so failing to enable it is not fatal.
+            let subtree_control = root.join("cgroup.subtree_control");
+            match read_to_string(&subtree_control) {
+                Ok(controllers) if controllers.split_whitespace().any(|c| c == "pids") => {}
+                _ => {
+                    if let Err(err) = write(&subtree_control, b"+pids\n") {
+                        log::warn!("write +pids to {:?}: {:#}", &subtree_control, err);
+                    }
+                }
+            }
+        }
+        let pid_namespace = pid_namespace()?;
         let mut rng = thread_rng();
         for _ in 0..16 {
-            let dir = root.join(format!("omegajail_{:016x}", rng.gen::<u64>()));
+            // The pid of this process and its namespace are part of the name so that the cgroup
+            // can be garbage collected if this process dies without removing it.
+            let dir = root.join(format!(
+                "omegajail_{}_{}_{:016x}",
+                getpid(),
+                pid_namespace,
+                rng.gen::<u64>()
+            ));
             if let Err(err) = create_dir(&dir) {
                 if err.kind() == ErrorKind::AlreadyExists {
                     continue;
                 }
                 bail!("create_dir({:?}): {:#}", &dir, err);
             }
-            return Ok(CGroup { path: dir, v2: v2 });
+            return Ok(CGroup { path: dir, v2 });
         }
 
         bail!("could not create a cgroup in {:?} after 16 rounds", root);
@@ -70,6 +88,80 @@ impl CGroup {
             .with_context(|| anyhow!("write {} to {:?}", limit, &memory_max_path))
     }
 
+    pub(crate) fn set_pids_limit(&self, limit: u64) -> Result<()> {
+        let pids_max_path = self.path.join("pids.max");
+        write(&pids_max_path, format!("{}", limit))
+            .with_context(|| anyhow!("write {} to {:?}", limit, &pids_max_path))
+    }
+
+    /// Returns whether any process in the cgroup failed to fork because of the pids limit. Returns
+    /// false if the cgroup does not have the pids controller.
+    pub(crate) fn pids_limit_exceeded(&self) -> Result<bool> {
+        Ok(read_flat_keyed(&self.path.join("pids.events"), "max")?.is_some_and(|count| count > 0))
+    }
+
+    /// Returns whether the OOM killer terminated any process in the cgroup because its memory
+    /// limit was reached. Returns false if the cgroup does not have the memory controller.
+    pub(crate) fn oom_killed(&self) -> Result<bool> {
+        if self.v2 {
+            return Ok(
+                read_flat_keyed(&self.path.join("memory.events"), "oom_kill")?
+                    .is_some_and(|count| count > 0),
+            );
+        }
+        // Older kernels don't report oom_kill in v1, so the best approximation is whether the
+        // limit was ever hit.
+        match read_flat_keyed(&self.path.join("memory.oom_control"), "oom_kill")? {
+            Some(count) => Ok(count > 0),
+            None => Ok(read_flat_keyed(&self.path.join("memory.failcnt"), "")?
+                .is_some_and(|count| count > 0)),
+        }
+    }
+
+    /// Returns the peak memory usage of all the processes in the cgroup, in bytes. Returns `None`
+    /// if the cgroup does not have the memory controller, or the kernel is too old to report it.
+    pub(crate) fn memory_peak(&self) -> Result<Option<u64>> {
+        read_flat_keyed(
+            &self.path.join(if self.v2 {
+                "memory.peak"
+            } else {
+                "memory.max_usage_in_bytes"
+            }),
+            "",
+        )
+    }
+
+    /// Opens the `cpu.stat` file of the cgroup, which has the CPU usage of all the processes in
+    /// it. Only cgroup v2 has this file.
+    pub(crate) fn open_cpu_stat(&self) -> Result<Option<File>> {
+        if !self.v2 {
+            return Ok(None);
+        }
+        let cpu_stat_path = self.path.join("cpu.stat");
+        Ok(Some(
+            File::open(&cpu_stat_path).with_context(|| anyhow!("open {:?}", &cpu_stat_path))?,
+        ))
+    }
+
+    /// Opens the files needed to kill all the processes in the cgroup at once. Only cgroup v2 on
+    /// kernels that support `cgroup.kill` (5.14+) can do this.
+    pub(crate) fn open_killer(&self) -> Result<Option<CGroupKiller>> {
+        if !self.v2 {
+            return Ok(None);
+        }
+        let kill_path = self.path.join("cgroup.kill");
+        let kill = match OpenOptions::new().write(true).open(&kill_path) {
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            result => result.with_context(|| anyhow!("open {:?}", &kill_path))?,
+        };
+        let freeze_path = self.path.join("cgroup.freeze");
+        let freeze = OpenOptions::new()
+            .write(true)
+            .open(&freeze_path)
+            .with_context(|| anyhow!("open {:?}", &freeze_path))?;
+        Ok(Some(CGroupKiller { freeze, kill }))
+    }
+
     pub(crate) fn is_cgroup_v2() -> bool {
         return Path::new("/sys/fs/cgroup/cgroup.controllers").exists();
     }
@@ -77,8 +169,332 @@ impl CGroup {
 
 impl Drop for CGroup {
     fn drop(&mut self) {
-        if let Err(err) = remove_dir(&self.path) {
-            log::error!("remove_dir({:?}): {:#}", &self.path, err);
+        if let Err(err) = remove_cgroup(&self.path, self.v2) {
+            log::error!("remove cgroup {:?}: {:#}", &self.path, err);
+        }
+    }
+}
+
+fn cgroup_root<P1, P2>(subsystem: P1, cgroup_path: P2) -> Result<PathBuf>
+where
+    P1: Debug + AsRef<Path>,
+    P2: Debug + AsRef<Path>,
+{
+    Ok(PathBuf::from("/sys/fs/cgroup").join(&subsystem).join(
+        if cgroup_path.as_ref().is_absolute() {
+            cgroup_path
+                .as_ref()
+                .strip_prefix("/")
+                .with_context(|| anyhow!("relativize {:?}", &cgroup_path))?
+        } else {
+            cgroup_path.as_ref()
+        },
+    ))
+}
+
+/// Kills any processes that remain in the cgroup at `path` and removes it.
+fn remove_cgroup(path: &Path, v2: bool) -> Result<()> {
+    match remove_dir(path) {
+        Err(err) if err.raw_os_error() == Some(libc::EBUSY) => {}
+        result => return result.with_context(|| anyhow!("remove_dir({:?})", path)),
+    }
+    if !v2 || write(path.join("cgroup.kill"), b"1").is_err() {
+        let procs_path = path.join("cgroup.procs");
+        for pid in read_to_string(&procs_path)
+            .with_context(|| anyhow!("read {:?}", &procs_path))?
+            .lines()
+        {
+            let pid = Pid::from_raw(
+                pid.parse()
+                    .with_context(|| anyhow!("parse {:?} in {:?}", pid, &procs_path))?,
+            );
+            if let Err(err) = kill(pid, Signal::SIGKILL) {
+                log::warn!("kill({}, SIGKILL): {:#}", pid, err);
+            }
+        }
+    }
+    // The killed processes take a little while to leave the cgroup.
+    for _ in 0..100 {
+        match remove_dir(path) {
+            Err(err) if err.raw_os_error() == Some(libc::EBUSY) => {
+                sleep(Duration::from_millis(10));
+            }
+            result => return result.with_context(|| anyhow!("remove_dir({:?})", path)),
         }
     }
+    bail!("processes in {:?} did not exit after being killed", path);
+}
+
+/// Returns the inode of the pid namespace of this process, which identifies it.
+fn pid_namespace() -> Result<u64> {
+    Ok(metadata("/proc/self/ns/pid")
+        .context("stat /proc/self/ns/pid")?
+        .ino())
+}
+
+/// Returns whether the cgroup called `name`, which was created `age` ago, was created by an
+/// omegajail process that is no longer running. Pids are only meaningful within `pid_namespace`,
+/// so cgroups created in other pid namespaces (or by older versions, which did not record their
+/// owner) are only considered orphaned once they are older than `max_age`.
+fn is_orphaned(name: &str, pid_namespace: u64, age: Duration, max_age: Duration) -> bool {
+    let name = match name.strip_prefix("omegajail_") {
+        Some(name) => name,
+        None => return false,
+    };
+    let parts: Vec<&str> = name.split('_').collect();
+    if let [owner, owner_namespace, _] = parts[..] {
+        if let (Ok(owner), Ok(owner_namespace)) = (owner.parse(), owner_namespace.parse::<u64>()) {
+            if owner_namespace == pid_namespace {
+                return kill(Pid::from_raw(owner), None) == Err(Errno::ESRCH);
+            }
+        }
+    }
+    age >= max_age
+}
+
+/// Removes the cgroups under `cgroup_path` that were left behind by omegajail processes that died
+/// before cleaning up after themselves, killing any processes that remain in them. Cgroups whose
+/// owner cannot be checked are removed once they are older than `max_age`. Returns the number of
+/// cgroups that were removed.
+pub fn gc_cgroups<P: Debug + AsRef<Path>>(cgroup_path: P, max_age: Duration) -> Result<usize> {
+    let v2 = CGroup::is_cgroup_v2();
+    let pid_namespace = pid_namespace()?;
+    let now = SystemTime::now();
+    let mut removed = 0;
+    for subsystem in if v2 {
+        &[""][..]
+    } else {
+        &["memory", "pids"][..]
+    } {
+        let root = cgroup_root(subsystem, &cgroup_path)?;
+        let profiles = match read_dir(&root) {
+            Err(err) if err.kind() == ErrorKind::NotFound => continue,
+            result => result.with_context(|| anyhow!("read_dir({:?})", &root))?,
+        };
+        // Cgroups are created one level below the root, in a directory per seccomp profile.
+        for profile in profiles {
+            let profile = profile.with_context(|| anyhow!("read_dir({:?})", &root))?;
+            if !profile.file_type()?.is_dir() {
+                continue;
+            }
+            let profile_path = profile.path();
+            for entry in
+                read_dir(&profile_path).with_context(|| anyhow!("read_dir({:?})", &profile_path))?
+            {
+                let entry = entry.with_context(|| anyhow!("read_dir({:?})", &profile_path))?;
+                let path = entry.path();
+                let age = entry
+                    .metadata()
+                    .and_then(|metadata| metadata.modified())
+                    .with_context(|| anyhow!("stat {:?}", &path))?;
+                let age = now.duration_since(age).unwrap_or_default();
+                if !entry
+                    .file_name()
+                    .to_str()
+                    .is_some_and(|name| is_orphaned(name, pid_namespace, age, max_age))
+                {
+                    continue;
+                }
+                match remove_cgroup(&path, v2) {
+                    Ok(()) => {
+                        log::debug!("removed stale cgroup {:?}", &path);
+                        removed += 1;
+                    }
+                    Err(err) => log::warn!("remove stale cgroup {:?}: {:#}", &path, err),
+                }
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// Reads the value of `key` from a cgroup file with one `key value` pair per line. An empty `key`
+/// reads a file with a single value. Returns `None` if either the file or the key do not exist.
+fn read_flat_keyed(path: &Path, key: &str) -> Result<Option<u64>> {
+    let contents = match read_to_string(path) {
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        result => result.with_context(|| anyhow!("read {:?}", path))?,
+    };
+    for line in contents.lines() {
+        let value = if key.is_empty() {
+            line
+        } else {
+            match line.split_once(' ') {
+                Some((k, value)) if k == key => value,
+                _ => continue,
+            }
+        };
+        return Ok(Some(
+            value
+                .trim()
+                .parse()
+                .with_context(|| anyhow!("parse {:?} in {:?}", line, path))?,
+        ));
+    }
+    Ok(None)
+}
+
+/// Kills all the processes in a cgroup v2 at once, through its already-open `cgroup.freeze` and
+/// `cgroup.kill` files. Since the files are open, this can be used from within the sandbox.
+pub(crate) struct CGroupKiller {
+    pub freeze: File,
+    pub kill: File,
+}
+
+impl CGroupKiller {
+    /// Freezes the cgroup, so that none of its processes can run anymore and their resource usage
+    /// stops changing, and then sends SIGKILL to all of them. This also kills any processes that
+    /// would otherwise outlive the one that was started in the sandbox.
+    pub(crate) fn kill(&self) -> Result<()> {
+        (&self.freeze)
+            .write_all(b"1")
+            .context("write 1 to cgroup.freeze")?;
+        (&self.kill)
+            .write_all(b"1")
+            .context("write 1 to cgroup.kill")
+    }
+}
+
+/// The CPU usage of all the processes in a cgroup, as reported by its `cpu.stat` file.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct CpuStat {
+    pub user_time: Duration,
+    pub system_time: Duration,
+}
+
+impl CpuStat {
+    /// Reads the current CPU usage from an open `cpu.stat` file. The file can be read repeatedly.
+    pub(crate) fn read(cpu_stat: &File) -> Result<CpuStat> {
+        let mut buf = vec![0u8; 4096];
+        let n = cpu_stat.read_at(&mut buf, 0).context("read cpu.stat")?;
+        CpuStat::parse(std::str::from_utf8(&buf[..n]).context("parse cpu.stat")?)
+    }
+
+    fn parse(contents: &str) -> Result<CpuStat> {
+        let mut cpu_stat = CpuStat::default();
+        for line in contents.lines() {
+            let (key, value) = match line.split_once(' ') {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let field = match key {
+                "user_usec" => &mut cpu_stat.user_time,
+                "system_usec" => &mut cpu_stat.system_time,
+                _ => continue,
+            };
+            *field = Duration::from_micros(
+                value
+                    .parse()
+                    .with_context(|| anyhow!("parse cpu.stat entry {:?}", line))?,
+            );
+        }
+        Ok(cpu_stat)
+    }
+
+    /// The total CPU time consumed.
+    pub(crate) fn total(&self) -> Duration {
+        self.user_time + self.system_time
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read_to_string, write};
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use tempdir::TempDir;
+
+    use crate::jail::cgroups::{is_orphaned, pid_namespace, CGroup, CpuStat};
+
+    #[test]
+    fn test_parse_cpu_stat() -> Result<()> {
+        let cpu_stat = CpuStat::parse(
+            "usage_usec 1500250\nuser_usec 1250000\nsystem_usec 250250\nnr_periods 0\n",
+        )?;
+        assert_eq!(cpu_stat.user_time, Duration::from_micros(1250000));
+        assert_eq!(cpu_stat.system_time, Duration::from_micros(250250));
+        assert_eq!(cpu_stat.total(), Duration::from_micros(1500250));
+
+        assert!(CpuStat::parse("user_usec lots\n").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pids_limit() -> Result<()> {
+        let dir = TempDir::new("cgroup")?;
+        let cgroup = CGroup {
+            path: dir.path().to_path_buf(),
+            v2: true,
+        };
+
+        cgroup.set_pids_limit(16)?;
+        assert_eq!(read_to_string(dir.path().join("pids.max"))?, "16");
+
+        // Without pids.events, exceeding the limit is not detected.
+        assert!(!cgroup.pids_limit_exceeded()?);
+        write(dir.path().join("pids.events"), "max 0\n")?;
+        assert!(!cgroup.pids_limit_exceeded()?);
+        write(dir.path().join("pids.events"), "max 3\n")?;
+        assert!(cgroup.pids_limit_exceeded()?);
+        // This is not a real cgroup, so it should not be removed like one.
+        std::mem::forget(cgroup);
+
+        // Failing to set the limit is reported, so that the caller can fall back to RLIMIT_NPROC.
+        let cgroup = CGroup {
+            path: dir.path().join("nonexistent"),
+            v2: true,
+        };
+        assert!(cgroup.set_pids_limit(16).is_err());
+        std::mem::forget(cgroup);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_orphaned() -> Result<()> {
+        let pid_namespace = pid_namespace()?;
+        let young = Duration::from_secs(60);
+        let old = Duration::from_secs(2 * 86400);
+        let max_age = Duration::from_secs(86400);
+        assert!(!is_orphaned("cpp", pid_namespace, old, max_age));
+
+        let running = format!(
+            "omegajail_{}_{}_0123456789abcdef",
+            std::process::id(),
+            pid_namespace
+        );
+        assert!(!is_orphaned(&running, pid_namespace, old, max_age));
+        let dead = format!("omegajail_{}_{}_0123456789abcdef", i32::MAX, pid_namespace);
+        assert!(is_orphaned(&dead, pid_namespace, young, max_age));
+
+        // The owners of cgroups from other pid namespaces and older versions cannot be checked.
+        let other_namespace = format!(
+            "omegajail_{}_{}_0123456789abcdef",
+            i32::MAX,
+            pid_namespace + 1
+        );
+        assert!(!is_orphaned(
+            &other_namespace,
+            pid_namespace,
+            young,
+            max_age
+        ));
+        assert!(is_orphaned(&other_namespace, pid_namespace, old, max_age));
+        assert!(!is_orphaned(
+            "omegajail_0123456789abcdef",
+            pid_namespace,
+            young,
+            max_age
+        ));
+        assert!(is_orphaned(
+            "omegajail_0123456789abcdef",
+            pid_namespace,
+            old,
+            max_age
+        ));
+
+        Ok(())
+    }
 }
@@ -1,5 +1,6 @@
 use std::fs::File;
 use std::io::{ErrorKind, Read};
+use std::os::unix::io::AsRawFd;
 use std::os::unix::net::UnixStream;
 
 use anyhow::{anyhow, bail, Context, Result};
@@ -8,6 +9,7 @@ use nix::sys::resource::{setrlimit, Resource};
 use nix::sys::signal::{sigprocmask, SigSet, SigmaskHow};
 use nix::unistd::execve;
 
+use crate::jail::audit::audit_filter;
 use crate::jail::options::JailOptions;
 use crate::jail::{write_message, SendSeccompFDEvent};
 use crate::sys::{seccomp_set_mode_filter, seccomp_set_mode_filter_with_listener, SendFile};
@@ -106,7 +108,11 @@ fn setup_signal_handlers() -> Result<()> {
 }
 
 fn setup_seccomp_bpf(child_sock: &mut UnixStream, opts: &JailOptions) -> Result<()> {
-    match seccomp_set_mode_filter_with_listener(&opts.seccomp_bpf_filter_notify_contents) {
+    let filter = match &opts.audit {
+        Some(_) => audit_filter(child_sock.as_raw_fd()).context("compile audit filter")?,
+        None => opts.seccomp_bpf_filter_notify_contents.clone(),
+    };
+    match seccomp_set_mode_filter_with_listener(&filter) {
         Ok(fd) => {
             let write_message_result =
                 write_message(child_sock, SendSeccompFDEvent { fd_available: true });
@@ -117,6 +123,12 @@ fn setup_seccomp_bpf(child_sock: &mut UnixStream, opts: &JailOptions) -> Result<
             return Ok(());
         }
         Err(err) => {
+            if opts.audit.is_some() {
+                // The sigsys filter would not record anything, so never fall back to it.
+                return Err(err.context(
+                    "--audit-syscalls requires seccomp user notifications, which are not available",
+                ));
+            }
             if opts.allow_sigsys_fallback {
                 match err.downcast_ref::<Errno>() {
                     Some(&Errno::ENOSYS) => {
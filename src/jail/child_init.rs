@@ -1,8 +1,9 @@
-use std::fs::{create_dir_all, metadata, File};
+use std::fs::{create_dir, create_dir_all, metadata, File};
 use std::ops::Add;
 use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::os::unix::net::UnixStream;
+use std::path::Path;
 use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, bail, Context, Result};
@@ -14,20 +15,26 @@ use nix::sys::epoll::{
     epoll_create1, epoll_ctl, epoll_wait, EpollCreateFlags, EpollEvent, EpollFlags, EpollOp,
 };
 use nix::sys::signal::{kill, Signal};
+use nix::sys::statvfs::{statvfs, FsFlags};
 use nix::sys::wait::WaitPidFlag;
 use nix::unistd::{
     chdir, chroot, close, dup2, fchdir, fork, getgid, getuid, pipe2, pivot_root, sethostname,
     setresgid, setresuid, ForkResult, Pid,
 };
 
-use crate::jail::options::{JailOptions, Stdio};
+use crate::jail::audit::SyscallAudit;
+use crate::jail::cgroups::{CGroupKiller, CpuStat};
+use crate::jail::extract::send_extracted_files;
+use crate::jail::options::{HomeOverlay, JailOptions, MountArgs, Stdio};
+use crate::jail::verdict;
 use crate::jail::{
     read_message, write_message, ParentSetupDoneEvent, SendSeccompFDEvent, SetupCgroupRequest,
     SetupCgroupResponse,
 };
 use crate::sys::{
-    capset, close_range, pidfd_open, seccomp_get_notification_size, seccomp_read_notification,
-    set_all_securebits, set_no_new_privs, waitid, Capabilities, RecvFile, SendFile, WaitStatus,
+    capset, close_range, pidfd_open, prlimit, seccomp_continue_notification,
+    seccomp_get_notification_size, seccomp_read_notification, set_all_securebits, set_no_new_privs,
+    waitid, Capabilities, KilledBy, RecvFile, ResourceUsage, SendFile, Verdict, WaitStatus,
     WaitidStatus, WaitidWhich,
 };
 
@@ -35,7 +42,7 @@ use crate::sys::{
 const NONE: Option<&'static [u8]> = None;
 
 pub(crate) fn run(mut parent_jail_sock: UnixStream, opts: JailOptions) -> Result<()> {
-    set_cpu_affinity().context("set cpu affinity")?;
+    set_cpu_affinity(opts.cpu).context("set cpu affinity")?;
 
     read_message::<ParentSetupDoneEvent>(&mut parent_jail_sock)
         .context("wait for parent setup done")?;
@@ -88,7 +95,7 @@ pub(crate) fn run(mut parent_jail_sock: UnixStream, opts: JailOptions) -> Result
             let _ = close(libc::STDIN_FILENO);
             let _ = close(libc::STDOUT_FILENO);
 
-            {
+            let (cpu_stat, killer) = {
                 write_message(&mut parent_jail_sock, SetupCgroupRequest {})
                     .context("write setup cgroup request")?;
                 if !opts.disable_sandboxing {
@@ -98,15 +105,78 @@ pub(crate) fn run(mut parent_jail_sock: UnixStream, opts: JailOptions) -> Result
                         .send_file(child_pidfd)
                         .context("send child pidfd")?;
                 }
-                read_message::<SetupCgroupResponse>(&mut parent_jail_sock)
+                let response = read_message::<SetupCgroupResponse>(&mut parent_jail_sock)
                     .context("read setup cgroup response")?;
-            }
+                if let Some(pids_limit) = opts.pids_limit {
+                    if !response.pids_limit_enforced {
+                        // RLIMIT_NPROC counts all the processes of the user in the namespace,
+                        // which includes this one.
+                        prlimit(child, libc::RLIMIT_NPROC, pids_limit + 1)
+                            .context("set pids limit")?;
+                    }
+                }
+                if let Some(memory_limit) = opts.memory_limit {
+                    if opts.use_cgroups_for_memory_limit && !response.memory_limit_enforced {
+                        if opts.require_cgroups_for_memory_limit {
+                            bail!("memory limit could not be enforced through a cgroup");
+                        }
+                        log::warn!(
+                            "memory limit not enforced through a cgroup, falling back to \
+                             RLIMIT_AS"
+                        );
+                        prlimit(child, libc::RLIMIT_AS, memory_limit)
+                            .context("set memory limit")?;
+                    }
+                }
+                let cpu_stat = if response.cpu_stat_available {
+                    Some(
+                        parent_jail_sock
+                            .recv_file()
+                            .context("receive cgroup cpu.stat")?,
+                    )
+                } else {
+                    None
+                };
+                let killer = if response.killer_available {
+                    Some(CGroupKiller {
+                        freeze: parent_jail_sock
+                            .recv_file()
+                            .context("receive cgroup.freeze")?,
+                        kill: parent_jail_sock
+                            .recv_file()
+                            .context("receive cgroup.kill")?,
+                    })
+                } else {
+                    None
+                };
+                (cpu_stat, killer)
+            };
             let child_start = Instant::now();
             let deadline = child_start.add(opts.wall_time_limit);
             std::mem::drop(write_pipe);
 
-            let status = wait_child(child, jail_sock, child_start, deadline, &opts);
+            let (status, audit) = wait_child(
+                child,
+                jail_sock,
+                child_start,
+                deadline,
+                cpu_stat.as_ref(),
+                killer.as_ref(),
+                &opts,
+            );
             write_message(&mut parent_jail_sock, status).context("write status")?;
+            if let Some(audit) = audit {
+                write_message(&mut parent_jail_sock, audit).context("write syscall audit")?;
+            }
+            if !opts.extracts.is_empty() {
+                let home = if opts.disable_sandboxing {
+                    opts.homedir.as_path()
+                } else {
+                    Path::new("/home")
+                };
+                send_extracted_files(&mut parent_jail_sock, home, &opts.extracts)
+                    .context("send extracted files")?;
+            }
         }
         ForkResult::Child => {
             std::mem::drop(parent_jail_sock);
@@ -124,7 +194,16 @@ pub(crate) fn run(mut parent_jail_sock: UnixStream, opts: JailOptions) -> Result
     Ok(())
 }
 
-fn set_cpu_affinity() -> Result<()> {
+fn set_cpu_affinity(cpu: Option<usize>) -> Result<()> {
+    if let Some(cpu) = cpu {
+        let mut cpu_set = CpuSet::new();
+        cpu_set
+            .set(cpu)
+            .with_context(|| anyhow!("cpu_set.set({})", cpu))?;
+        sched_setaffinity(Pid::this(), &cpu_set)
+            .with_context(|| anyhow!("sched_setaffinity({})", cpu))?;
+        return Ok(());
+    }
     // Set the processor affinity mask to a single core. If this process already
     // has an affinity mask set with more than one core set, limit it to the
     // first one in the set.
@@ -163,6 +242,10 @@ fn setup_mount_namespace(opts: &JailOptions) -> Result<()> {
     unshare(CloneFlags::CLONE_NEWNS).context("unshare(CLONE_NEWNS)")?;
     mount(NONE, "/", NONE, MsFlags::MS_REC | MsFlags::MS_PRIVATE, NONE)
         .context("mount / as private")?;
+    if let Some(home_overlay) = &opts.home_overlay {
+        setup_home_overlay(&opts.rootfs.join("home"), home_overlay)
+            .context("setup /home overlay")?;
+    }
     for mount_args in &opts.mounts {
         if !mount_args.target.exists() {
             if !mount_args.flags.contains(MsFlags::MS_BIND) {
@@ -194,6 +277,9 @@ fn setup_mount_namespace(opts: &JailOptions) -> Result<()> {
             mount_args.data.as_deref(),
         )
         .with_context(|| format!("mount({:?})", &mount_args))?;
+        if mount_args.flags.contains(MsFlags::MS_BIND) {
+            remount_bind(mount_args).with_context(|| format!("remount({:?})", &mount_args))?;
+        }
     }
 
     // Now we can pivot_root.
@@ -232,14 +318,6 @@ fn setup_mount_namespace(opts: &JailOptions) -> Result<()> {
         NONE,
     )
     .context("remount / as read-only")?;
-    mount(
-        NONE,
-        "/tmp",
-        Some("tmpfs"),
-        MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
-        Some("size=67108864,mode=1777"),
-    )
-    .context("mount /tmp")?;
     chdir("/home").context("chdir(\"/home\")")?;
 
     // Redirect stdio.
@@ -287,6 +365,91 @@ fn setup_mount_namespace(opts: &JailOptions) -> Result<()> {
     Ok(())
 }
 
+/// Applies the flags of a bind mount. Flags other than `MS_REC` are ignored when a bind mount is
+/// created, so they only take effect after the mount is remounted with them.
+fn remount_bind(mount_args: &MountArgs) -> Result<()> {
+    let flags = mount_args.flags
+        & (MsFlags::MS_RDONLY | MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC);
+    if flags.is_empty() {
+        return Ok(());
+    }
+    // Flags of the source mount are locked in a user namespace, so the remount must keep them.
+    let source_flags = statvfs(&mount_args.target)
+        .with_context(|| anyhow!("statvfs({:?})", &mount_args.target))?
+        .flags();
+    let mut locked_flags = MsFlags::empty();
+    for (fs_flag, ms_flag) in [
+        (FsFlags::ST_RDONLY, MsFlags::MS_RDONLY),
+        (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+        (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+        (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+        (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+        (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+        (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+    ] {
+        if source_flags.contains(fs_flag) {
+            locked_flags.insert(ms_flag);
+        }
+    }
+    mount(
+        NONE,
+        &mount_args.target,
+        NONE,
+        MsFlags::MS_REMOUNT | MsFlags::MS_BIND | flags | locked_flags,
+        NONE,
+    )
+    .context("mount")?;
+
+    Ok(())
+}
+
+/// Mounts an overlay at `home`, with the host's directory as the lower layer and the upper layer in
+/// a size-limited tmpfs. Since the tmpfs only lives in this mount namespace, all the writes are
+/// discarded once the sandbox exits.
+fn setup_home_overlay(home: &Path, home_overlay: &HomeOverlay) -> Result<()> {
+    if !home.exists() {
+        create_dir_all(home).with_context(|| format!("create overlay target {:?}", home))?;
+    }
+    mount(
+        NONE,
+        home,
+        Some("tmpfs"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+        Some(
+            format!(
+                "size={},nr_inodes={},mode=755",
+                home_overlay.size, home_overlay.inodes
+            )
+            .as_str(),
+        ),
+    )
+    .with_context(|| anyhow!("mount tmpfs at {:?}", home))?;
+    // The upper and work directories are still reachable by the overlay after it is mounted on top
+    // of them.
+    let upperdir = home.join("upper");
+    let workdir = home.join("work");
+    create_dir(&upperdir).with_context(|| anyhow!("create_dir({:?})", &upperdir))?;
+    create_dir(&workdir).with_context(|| anyhow!("create_dir({:?})", &workdir))?;
+    mount(
+        Some("overlay"),
+        home,
+        Some("overlay"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+        Some(
+            format!(
+                "lowerdir={},upperdir={},workdir={}",
+                home_overlay.lowerdir.display(),
+                upperdir.display(),
+                workdir.display()
+            )
+            .as_str(),
+        ),
+    )
+    .with_context(|| anyhow!("mount overlay at {:?}", home))?;
+
+    Ok(())
+}
+
 fn setup_unsandboxed_filesystem(opts: &JailOptions) -> Result<()> {
     chdir(&opts.homedir).with_context(|| anyhow!("chdir({:?})", opts.homedir))?;
 
@@ -371,27 +534,37 @@ fn wait_child(
     mut jail_sock: UnixStream,
     child_start: Instant,
     deadline: Instant,
+    cpu_stat: Option<&File>,
+    killer: Option<&CGroupKiller>,
     opts: &JailOptions,
-) -> WaitidStatus {
-    let override_status = if !opts.disable_sandboxing {
+) -> (WaitidStatus, Option<SyscallAudit>) {
+    let mut audit = opts.audit.as_ref().map(|_| SyscallAudit::default());
+    let (override_status, killed_by) = if !opts.disable_sandboxing {
         let seccomp_fd = match wait_receive_seccomp_fd(&mut jail_sock) {
             Err(err) => {
                 log::error!("receive seccomp fd: {:#}", err);
-                let _ = kill(child, Signal::SIGKILL);
+                kill_sandbox(child, killer);
                 None
             }
             Ok(seccomp_fd) => seccomp_fd,
         };
-        match wait_read_seccomp_notification(child, deadline, seccomp_fd) {
+        match wait_read_seccomp_notification(
+            child,
+            deadline,
+            opts.time_limit.zip(cpu_stat),
+            killer,
+            seccomp_fd,
+            audit.as_mut(),
+        ) {
             Err(err) => {
                 log::error!("read seccomp notification: {:#}", err);
-                let _ = kill(child, Signal::SIGKILL);
-                None
+                kill_sandbox(child, killer);
+                (None, None)
             }
             Ok(result) => result,
         }
     } else {
-        None
+        (None, None)
     };
 
     let mut status = match waitid(
@@ -400,24 +573,63 @@ fn wait_child(
     ) {
         Err(err) => {
             log::error!("waitid(Pid({}), WEXITED|WSTOPPED): {:#}", child, err);
-            let _ = kill(child, Signal::SIGKILL);
-            WaitidStatus {
+            kill_sandbox(child, killer);
+            let status = WaitidStatus {
                 status: WaitStatus::Signaled(child, Signal::SIGKILL),
+                verdict: Verdict::JudgeError,
                 user_time: Duration::ZERO,
                 system_time: Duration::ZERO,
                 wall_time: Instant::now().duration_since(child_start),
                 max_rss: 0,
-            }
+                cgroup_max_memory: None,
+                rusage: ResourceUsage::default(),
+                killed_by: None,
+                pids_limit_exceeded: false,
+                oom_killed: false,
+                extracted: vec![],
+            };
+            return (status, audit);
         }
         Ok(status) => status,
     };
     status.wall_time = Instant::now().duration_since(child_start);
+    if let Some(cpu_stat) = cpu_stat {
+        // Report the usage of all the processes in the cgroup, not only the ones that were waited
+        // for.
+        match CpuStat::read(cpu_stat) {
+            Err(err) => {
+                log::error!("read cgroup cpu.stat: {:#}", err);
+            }
+            Ok(cpu_stat) => {
+                status.user_time = cpu_stat.user_time;
+                status.system_time = cpu_stat.system_time;
+            }
+        }
+    }
     status.max_rss = status.max_rss.saturating_sub(opts.vm_memory_size_in_bytes);
     if let Some(s) = override_status {
         status.status = s;
     }
+    status.killed_by = killed_by;
+    status.verdict = verdict::classify(
+        &status,
+        opts.time_limit,
+        opts.requested_memory_limit,
+        opts.output_limit,
+    );
+
+    (status, audit)
+}
 
-    status
+/// Kills the child and, if the cgroup can be killed as a whole, every other process in the sandbox.
+fn kill_sandbox(child: Pid, killer: Option<&CGroupKiller>) {
+    if let Some(killer) = killer {
+        match killer.kill() {
+            Ok(()) => return,
+            Err(err) => log::error!("kill cgroup: {:#}", err),
+        }
+    }
+    let _ = kill(child, Signal::SIGKILL);
 }
 
 fn wait_receive_seccomp_fd(jail_sock: &mut UnixStream) -> Result<Option<File>> {
@@ -430,11 +642,19 @@ fn wait_receive_seccomp_fd(jail_sock: &mut UnixStream) -> Result<Option<File>> {
     }
 }
 
+/// Waits until the child exits, invokes a forbidden syscall, or the deadline expires. If
+/// `cpu_time_limit` is provided, the CPU usage of the cgroup is also checked against the time limit.
+/// If `audit` is provided, syscalls are recorded in it and allowed to continue instead.
+/// Returns the status that should be reported instead of the one from `waitid(2)` (if any), and
+/// whether the child was killed because of the wall time limit.
 fn wait_read_seccomp_notification(
     child: Pid,
     deadline: Instant,
+    cpu_time_limit: Option<(Duration, &File)>,
+    killer: Option<&CGroupKiller>,
     seccomp_file: Option<File>,
-) -> Result<Option<WaitStatus>> {
+    mut audit: Option<&mut SyscallAudit>,
+) -> Result<(Option<WaitStatus>, Option<KilledBy>)> {
     let epoll_file = unsafe {
         File::from_raw_fd(epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC).context("epoll_create1")?)
     };
@@ -469,10 +689,26 @@ fn wait_read_seccomp_notification(
 
     let mut events = vec![EpollEvent::empty(); 2];
     loop {
-        let timeout = deadline.saturating_duration_since(Instant::now());
+        let mut timeout = deadline.saturating_duration_since(Instant::now());
+        if let Some((time_limit, cpu_stat)) = cpu_time_limit {
+            let usage = CpuStat::read(cpu_stat)
+                .context("read cgroup cpu.stat")?
+                .total();
+            if usage > time_limit {
+                kill_sandbox(child, killer);
+                return Ok((Some(WaitStatus::Signaled(child, Signal::SIGXCPU)), None));
+            }
+            // All the sandboxed processes are pinned to a single core, so the CPU usage cannot grow
+            // faster than the wall time.
+            timeout = timeout.min((time_limit - usage).max(Duration::from_millis(1)));
+        }
         if timeout == Duration::ZERO {
-            kill(child, Signal::SIGKILL).context("kill child")?;
-            return Ok(Some(WaitStatus::Signaled(child, Signal::SIGXCPU)));
+            kill_sandbox(child, killer);
+            // This is still reported as SIGXCPU for compatibility with existing consumers.
+            return Ok((
+                Some(WaitStatus::Signaled(child, Signal::SIGXCPU)),
+                Some(KilledBy::WallTimeLimit),
+            ));
         }
         let nfds = match epoll_wait(
             epoll_file.as_raw_fd(),
@@ -488,14 +724,25 @@ fn wait_read_seccomp_notification(
             Ok(nfds) => nfds,
         };
         for i in 0..nfds {
-            if events[i].data() == child_pidfd.as_raw_fd().try_into()? {
-                return Ok(None);
+            if events[i].data() == u64::try_from(child_pidfd.as_raw_fd())? {
+                return Ok((None, None));
             } else {
                 let notification =
                     seccomp_read_notification(seccomp_fd, &mut notification_contents)
                         .context("seccomp_read_notification")?;
-                kill(child, Signal::SIGKILL).context("kill child")?;
-                return Ok(Some(WaitStatus::Syscalled(child, notification.data.nr)));
+                if let Some(audit) = audit.as_mut() {
+                    audit.record(&notification.data);
+                    if let Err(err) = seccomp_continue_notification(seccomp_fd, notification.id) {
+                        // The process may have been killed while the syscall was waiting.
+                        log::warn!("continue syscall {}: {:#}", notification.data.nr, err);
+                    }
+                    continue;
+                }
+                kill_sandbox(child, killer);
+                return Ok((
+                    Some(WaitStatus::Syscalled(child, notification.data.nr)),
+                    None,
+                ));
             }
         }
     }
@@ -0,0 +1,103 @@
+//! A pool of CPU cores that are handed out exclusively to concurrently running sandboxes.
+
+use std::sync::{Arc, Condvar, Mutex};
+
+use anyhow::{anyhow, bail, Context, Result};
+use nix::sched::{sched_getaffinity, CpuSet};
+use nix::unistd::Pid;
+
+/// A set of CPU cores, each one of which can be leased by a single [`Jail`](crate::jail::Jail) at
+/// a time, so that sandboxes that run concurrently don't compete for the same core.
+///
+/// Cloning a `CpuPool` returns another handle to the same set of cores.
+#[derive(Clone)]
+pub struct CpuPool {
+    inner: Arc<(Mutex<Vec<usize>>, Condvar)>,
+}
+
+impl CpuPool {
+    /// Constructs a new `CpuPool` with the cores in `cpus`.
+    pub fn new(cpus: Vec<usize>) -> CpuPool {
+        CpuPool {
+            inner: Arc::new((Mutex::new(cpus), Condvar::new())),
+        }
+    }
+
+    /// Constructs a new `CpuPool` with all the cores that this process is allowed to run on.
+    pub fn from_affinity() -> Result<CpuPool> {
+        let cpu_set = sched_getaffinity(Pid::this()).context("sched_getaffinity")?;
+        let mut cpus = Vec::new();
+        for i in 0..CpuSet::count() {
+            if cpu_set
+                .is_set(i)
+                .with_context(|| anyhow!("cpu_set.is_set({})", i))?
+            {
+                cpus.push(i);
+            }
+        }
+        if cpus.is_empty() {
+            bail!("no cpus in the affinity mask");
+        }
+        Ok(CpuPool::new(cpus))
+    }
+
+    /// Leases a core, waiting until one is available.
+    pub fn acquire(&self) -> CpuLease {
+        let (cpus, available) = &*self.inner;
+        let mut cpus = available
+            .wait_while(cpus.lock().unwrap(), |cpus| cpus.is_empty())
+            .unwrap();
+        CpuLease {
+            pool: self.clone(),
+            cpu: cpus.pop().unwrap(),
+        }
+    }
+
+    /// Leases a core if one is available right now.
+    pub fn try_acquire(&self) -> Option<CpuLease> {
+        let cpu = self.inner.0.lock().unwrap().pop()?;
+        Some(CpuLease {
+            pool: self.clone(),
+            cpu,
+        })
+    }
+}
+
+/// A core leased from a [`CpuPool`]. The core is returned to the pool when this is dropped.
+pub struct CpuLease {
+    pool: CpuPool,
+    cpu: usize,
+}
+
+impl CpuLease {
+    /// The index of the leased core.
+    pub fn cpu(&self) -> usize {
+        self.cpu
+    }
+}
+
+impl Drop for CpuLease {
+    fn drop(&mut self) {
+        let (cpus, available) = &*self.pool.inner;
+        cpus.lock().unwrap().push(self.cpu);
+        available.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::jail::CpuPool;
+
+    #[test]
+    fn test_cpu_pool() {
+        let pool = CpuPool::new(vec![2, 3]);
+        let first = pool.acquire();
+        let second = pool.try_acquire().expect("a second cpu is available");
+        assert_ne!(first.cpu(), second.cpu());
+        assert!(pool.try_acquire().is_none());
+
+        let cpu = second.cpu();
+        std::mem::drop(second);
+        assert_eq!(pool.acquire().cpu(), cpu);
+    }
+}
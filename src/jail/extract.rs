@@ -0,0 +1,206 @@
+//! Copying of files written by the sandboxed process out of the sandbox.
+//!
+//! Once the sandboxed process exits, the sandboxed init opens each one of the requested files and
+//! sends them to the parent process, which copies them to their destination. Files are opened
+//! without following any symlinks, so that the sandboxed process cannot trick omegajail into
+//! copying files that it could not write to.
+
+use std::fs::File;
+use std::io::{copy, Read};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use nix::fcntl::{openat, OFlag};
+use nix::sys::stat::{fstat, Mode, SFlag};
+use serde::{Deserialize, Serialize};
+
+use crate::jail::{read_message, write_message};
+use crate::sys::{ExtractedFile, RecvFile, SendFile};
+
+/// A file that is copied out of the sandbox once the sandboxed process exits.
+#[derive(Debug, Clone)]
+pub(crate) struct Extract {
+    /// The path of the file, relative to the sandbox's home directory.
+    pub path: PathBuf,
+    /// The path in the host where the file is copied to.
+    pub dest: PathBuf,
+}
+
+impl Extract {
+    /// Parses a `PATH:DEST` specification.
+    pub(crate) fn parse(spec: &str) -> Result<Extract> {
+        let (path, dest) = spec
+            .split_once(':')
+            .ok_or_else(|| anyhow!("{:?} is not in PATH:DEST format", spec))?;
+        let path = PathBuf::from(path);
+        if path.as_os_str().is_empty()
+            || !path
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        {
+            bail!(
+                "{:?} is not a relative path within the home directory",
+                path
+            );
+        }
+        if dest.is_empty() {
+            bail!("{:?} does not have a destination", spec);
+        }
+        Ok(Extract {
+            path,
+            dest: PathBuf::from(dest),
+        })
+    }
+}
+
+/// Sent by the sandboxed init after the status, followed by the files that could be opened.
+#[derive(Serialize, Deserialize)]
+struct ExtractFilesEvent {
+    /// For each requested file, `None` if it follows this message, or the reason why it could not
+    /// be opened.
+    errors: Vec<Option<String>>,
+}
+
+/// Opens the regular file at `path` beneath `home`, failing if any of its components is a symlink.
+fn open_beneath(home: &Path, path: &Path) -> Result<File> {
+    let flags = OFlag::O_RDONLY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC;
+    let mut dir = File::open(home).with_context(|| anyhow!("open {:?}", home))?;
+    let mut components = path.components().peekable();
+    while let Some(component) = components.next() {
+        let is_last = components.peek().is_none();
+        let fd = openat(
+            dir.as_raw_fd(),
+            component.as_os_str(),
+            if is_last {
+                // A FIFO would otherwise block until someone opens it for writing.
+                flags | OFlag::O_NONBLOCK
+            } else {
+                flags | OFlag::O_DIRECTORY
+            },
+            Mode::empty(),
+        )
+        .map_err(|err| match err {
+            nix::errno::Errno::ELOOP => anyhow!("{:?} is a symlink", component.as_os_str()),
+            err => anyhow!("open {:?}: {}", component.as_os_str(), err),
+        })?;
+        dir = unsafe { File::from_raw_fd(fd) };
+    }
+    let stat = fstat(dir.as_raw_fd()).context("fstat")?;
+    if SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT != SFlag::S_IFREG {
+        bail!("not a regular file");
+    }
+    Ok(dir)
+}
+
+/// Opens each one of `extracts` beneath `home` and sends them through `sock`.
+pub(crate) fn send_extracted_files(
+    sock: &mut UnixStream,
+    home: &Path,
+    extracts: &[Extract],
+) -> Result<()> {
+    let mut files = Vec::new();
+    let mut errors = Vec::with_capacity(extracts.len());
+    for extract in extracts {
+        match open_beneath(home, &extract.path) {
+            Ok(file) => {
+                files.push(file);
+                errors.push(None);
+            }
+            Err(err) => errors.push(Some(format!("{:#}", err))),
+        }
+    }
+    write_message(sock, ExtractFilesEvent { errors }).context("write extract files event")?;
+    for file in files {
+        sock.send_file(file).context("send extracted file")?;
+    }
+    Ok(())
+}
+
+/// Receives the files sent by [`send_extracted_files`] and copies them to their destinations.
+/// Files larger than `size_limit` are not copied.
+pub(crate) fn receive_extracted_files(
+    sock: &mut UnixStream,
+    extracts: &[Extract],
+    size_limit: u64,
+) -> Result<Vec<ExtractedFile>> {
+    let event = read_message::<ExtractFilesEvent>(sock).context("read extract files event")?;
+    if event.errors.len() != extracts.len() {
+        bail!(
+            "expected {} extracted files, got {}",
+            extracts.len(),
+            event.errors.len()
+        );
+    }
+    let mut extracted = Vec::with_capacity(extracts.len());
+    for (extract, error) in extracts.iter().zip(event.errors) {
+        let result = match error {
+            Some(error) => Err(error),
+            None => {
+                let file = sock.recv_file().context("receive extracted file")?;
+                copy_extracted_file(file, &extract.dest, size_limit)
+                    .map_err(|err| format!("{:#}", err))
+            }
+        };
+        extracted.push(ExtractedFile {
+            path: extract.path.to_string_lossy().into_owned(),
+            size: result.as_ref().ok().copied(),
+            error: result.err(),
+        });
+    }
+    Ok(extracted)
+}
+
+fn copy_extracted_file(file: File, dest: &Path, size_limit: u64) -> Result<u64> {
+    let size = file.metadata().context("stat")?.len();
+    if size > size_limit {
+        bail!("size {} exceeds the limit of {} bytes", size, size_limit);
+    }
+    let mut dest_file = File::create(dest).with_context(|| anyhow!("create {:?}", dest))?;
+    // The file could still grow if any process in the sandbox is still alive.
+    copy(&mut file.take(size_limit), &mut dest_file).with_context(|| anyhow!("copy to {:?}", dest))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir, write};
+    use std::os::unix::fs::symlink;
+    use std::path::Path;
+
+    use anyhow::Result;
+    use tempdir::TempDir;
+
+    use crate::jail::extract::{open_beneath, Extract};
+
+    #[test]
+    fn test_parse_extract() -> Result<()> {
+        let extract = Extract::parse("out/output.txt:/tmp/output.txt")?;
+        assert_eq!(extract.path, Path::new("out/output.txt"));
+        assert_eq!(extract.dest, Path::new("/tmp/output.txt"));
+
+        assert!(Extract::parse("output.txt").is_err());
+        assert!(Extract::parse("/etc/passwd:/tmp/passwd").is_err());
+        assert!(Extract::parse("../output.txt:/tmp/output.txt").is_err());
+        assert!(Extract::parse("output.txt:").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_beneath() -> Result<()> {
+        let home = TempDir::new("extract")?;
+        create_dir(home.path().join("out"))?;
+        write(home.path().join("out/output.txt"), "42\n")?;
+        symlink("/etc/passwd", home.path().join("passwd"))?;
+        symlink(home.path().join("out"), home.path().join("link"))?;
+
+        assert!(open_beneath(home.path(), Path::new("out/output.txt")).is_ok());
+        assert!(open_beneath(home.path(), Path::new("passwd")).is_err());
+        assert!(open_beneath(home.path(), Path::new("link/output.txt")).is_err());
+        assert!(open_beneath(home.path(), Path::new("out")).is_err());
+        assert!(open_beneath(home.path(), Path::new("missing")).is_err());
+
+        Ok(())
+    }
+}
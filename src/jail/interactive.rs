@@ -0,0 +1,227 @@
+//! Support for interactive problems, where a trusted interactor and the contestant's program
+//! communicate with each other through their stdin / stdout.
+
+use std::fs::File;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+
+use anyhow::{Context, Result};
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::unistd::pipe2;
+
+use crate::args;
+use crate::jail::{Command, Jail, JailResult, KilledBy, WaitStatus};
+
+fn pipe() -> Result<(File, File)> {
+    let (rfd, wfd) = pipe2(OFlag::O_CLOEXEC).context("create pipe")?;
+    Ok(unsafe { (File::from_raw_fd(rfd), File::from_raw_fd(wfd)) })
+}
+
+/// A builder for [`InteractiveJail`].
+pub struct InteractiveCommand {
+    interactor: args::Args,
+    contestant: args::Args,
+}
+
+impl InteractiveCommand {
+    /// Constructs a new `InteractiveCommand` for spawning an [`InteractiveJail`]. Each side keeps
+    /// its own limits, .meta file and stderr, but their stdin / stdout are connected to each other.
+    pub fn new(interactor: args::Args, contestant: args::Args) -> InteractiveCommand {
+        InteractiveCommand {
+            interactor,
+            contestant,
+        }
+    }
+
+    /// Executes both sides of the [`InteractiveJail`] as child, sandboxed processes, returning a
+    /// handle to them.
+    pub fn spawn(self) -> Result<InteractiveJail> {
+        let (contestant_stdin, interactor_stdout) = pipe()?;
+        let (interactor_stdin, contestant_stdout) = pipe()?;
+
+        let mut interactor = Command::new(self.interactor)
+            .stdin(interactor_stdin)
+            .stdout(interactor_stdout)
+            .spawn()
+            .context("spawn interactor")?;
+        let contestant = match Command::new(self.contestant)
+            .stdin(contestant_stdin)
+            .stdout(contestant_stdout)
+            .spawn()
+        {
+            Ok(contestant) => contestant,
+            Err(err) => {
+                // Don't leave the interactor running.
+                if let Err(err) = interactor.kill(KilledBy::Contestant) {
+                    log::error!("kill interactor: {:#}", err);
+                }
+                if let Err(err) = interactor.wait() {
+                    log::error!("wait interactor: {:#}", err);
+                }
+                return Err(err).context("spawn contestant");
+            }
+        };
+
+        Ok(InteractiveJail {
+            interactor,
+            contestant,
+        })
+    }
+}
+
+/// The results of both sides of an [`InteractiveJail`].
+#[derive(Debug)]
+pub struct InteractiveJailResult {
+    /// The result of the interactor.
+    pub interactor: JailResult,
+    /// The result of the contestant's program.
+    pub contestant: JailResult,
+}
+
+/// Representation of a running or exited pair of interactor / contestant sandboxed processes.
+///
+/// If the interactor exits first, the contestant's program is terminated. If the contestant's
+/// program is terminated by a signal (or a forbidden syscall), the interactor is terminated too.
+/// Otherwise, the interactor continues running (under its own limits) after the contestant's
+/// program exits so that it can process the end of its input.
+#[must_use]
+pub struct InteractiveJail {
+    interactor: Jail,
+    contestant: Jail,
+}
+
+impl InteractiveJail {
+    /// Waits for both sandboxed processes to exit completely, returning information about resource
+    /// usage and exit status of both processes.
+    ///
+    /// This function consumes the `InteractiveJail`, so it can only be used once.
+    pub fn wait(mut self) -> Result<InteractiveJailResult> {
+        // Each sandboxed init writes the result to its socket once its process has exited, so the
+        // first one to become readable is the first one to finish.
+        let mut fds = [
+            PollFd::new(self.interactor.parent_sock.as_raw_fd(), PollFlags::POLLIN),
+            PollFd::new(self.contestant.parent_sock.as_raw_fd(), PollFlags::POLLIN),
+        ];
+        loop {
+            match poll(&mut fds, -1) {
+                Err(Errno::EINTR) => {
+                    continue;
+                }
+                Err(err) => {
+                    log::error!("poll: {:#}", err);
+                    break;
+                }
+                Ok(_) => {
+                    break;
+                }
+            }
+        }
+
+        if !fds[0].revents().unwrap_or(PollFlags::empty()).is_empty() {
+            if let Err(err) = self.contestant.kill(KilledBy::Interactor) {
+                log::error!("kill contestant: {:#}", err);
+            }
+            let interactor = self.interactor.wait().context("wait interactor")?;
+            let contestant = self.contestant.wait().context("wait contestant")?;
+            return Ok(InteractiveJailResult {
+                interactor,
+                contestant,
+            });
+        }
+
+        let contestant = self.contestant.wait().context("wait contestant")?;
+        if !matches!(contestant.status, WaitStatus::Exited(_, _)) {
+            if let Err(err) = self.interactor.kill(KilledBy::Contestant) {
+                log::error!("kill interactor: {:#}", err);
+            }
+        }
+        let interactor = self.interactor.wait().context("wait interactor")?;
+        Ok(InteractiveJailResult {
+            interactor,
+            contestant,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::io::AsRawFd;
+
+    use anyhow::Result;
+    use nix::sys::signal::Signal;
+    use nix::unistd::Pid;
+    use tempdir::TempDir;
+
+    use crate::jail::interactive::{pipe, InteractiveJail, InteractiveJailResult};
+    use crate::jail::tests::{init, test_options};
+    use crate::jail::{Jail, KilledBy, Verdict, WaitStatus};
+
+    /// Runs `interactor` and `contestant` connected to each other, like
+    /// [`InteractiveCommand::spawn`](crate::jail::InteractiveCommand::spawn()) does.
+    fn run_interactive(interactor: &str, contestant: &str) -> Result<InteractiveJailResult> {
+        let interactor_dir = TempDir::new(interactor)?;
+        let contestant_dir = TempDir::new(contestant)?;
+        let (contestant_stdin, interactor_stdout) = pipe()?;
+        let (interactor_stdin, contestant_stdout) = pipe()?;
+
+        let mut interactor_options = test_options(interactor_dir.path(), interactor, "")?;
+        interactor_options.set_stdin_fd(interactor_stdin.as_raw_fd());
+        interactor_options.set_stdout_fd(interactor_stdout.as_raw_fd());
+        let mut contestant_options = test_options(contestant_dir.path(), contestant, "")?;
+        contestant_options.set_stdin_fd(contestant_stdin.as_raw_fd());
+        contestant_options.set_stdout_fd(contestant_stdout.as_raw_fd());
+
+        let jail = InteractiveJail {
+            interactor: Jail::new(interactor_options)?,
+            contestant: Jail::new(contestant_options)?,
+        };
+        jail.wait()
+    }
+
+    #[test]
+    fn test_interactor_crash() -> Result<()> {
+        init();
+        let result = run_interactive("abort", "sleep")?;
+
+        assert_eq!(
+            result.interactor.status,
+            WaitStatus::Signaled(Pid::from_raw(2), Signal::SIGABRT)
+        );
+        assert_eq!(result.interactor.verdict, Verdict::RuntimeError);
+        assert_eq!(result.interactor.killed_by, None);
+
+        assert_eq!(
+            result.contestant.status,
+            WaitStatus::Signaled(Pid::from_raw(2), Signal::SIGKILL)
+        );
+        assert_eq!(result.contestant.killed_by, Some(KilledBy::Interactor));
+        // Whether the contestant's answer was correct is up to the interactor.
+        assert_eq!(result.contestant.verdict, Verdict::Ok);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_contestant_crash() -> Result<()> {
+        init();
+        let result = run_interactive("sleep", "abort")?;
+
+        assert_eq!(
+            result.contestant.status,
+            WaitStatus::Signaled(Pid::from_raw(2), Signal::SIGABRT)
+        );
+        assert_eq!(result.contestant.verdict, Verdict::RuntimeError);
+        assert_eq!(result.contestant.killed_by, None);
+
+        assert_eq!(
+            result.interactor.status,
+            WaitStatus::Signaled(Pid::from_raw(2), Signal::SIGKILL)
+        );
+        assert_eq!(result.interactor.killed_by, Some(KilledBy::Contestant));
+        // The interactor was terminated well before its wall time limit.
+        assert!(result.interactor.wall_time < std::time::Duration::from_secs(2));
+
+        Ok(())
+    }
+}
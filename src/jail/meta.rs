@@ -0,0 +1,319 @@
+//! Writing of the .meta file with the result of the sandboxed process.
+
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+
+use crate::args::MetaFormat;
+use crate::jail::{JailResult, WaitStatus};
+
+/// The version of the JSON .meta format. This is bumped whenever a field is removed or changes
+/// meaning. Adding fields does not change the version.
+pub(crate) const JSON_META_VERSION: u32 = 1;
+
+/// The contents of a .meta file in JSON format, as described by `schemas/meta.schema.json`. The
+/// field names and units match the ones in the legacy `key:value` format.
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct JsonMeta {
+    version: u32,
+    verdict: &'static str,
+    time: u128,
+    time_sys: u128,
+    time_wall: u128,
+    mem: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mem_cgroup: Option<u64>,
+    minor_faults: u64,
+    major_faults: u64,
+    voluntary_context_switches: u64,
+    involuntary_context_switches: u64,
+    block_input_operations: u64,
+    block_output_operations: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signal: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    syscall: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    killed_by: Option<&'static str>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pids_limit_exceeded: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    oom_killed: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    extracted: Vec<JsonExtractedFile>,
+}
+
+/// A file extracted from the sandbox, in a .meta file in JSON format. Exactly one of `size` and
+/// `error` is present.
+#[derive(Serialize, Debug, PartialEq)]
+struct JsonExtractedFile {
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl JsonMeta {
+    pub(crate) fn new(status: &JailResult) -> Result<JsonMeta> {
+        let (exit_status, signal, syscall) = match status.status {
+            WaitStatus::Exited(_, exit_status) => (Some(exit_status), None, None),
+            WaitStatus::Signaled(_, signal) => (None, Some(signal.as_str()), None),
+            WaitStatus::Syscalled(_, syscall) => {
+                (None, Some("SIGSYS"), Some(syscall_name(syscall)?))
+            }
+        };
+        Ok(JsonMeta {
+            version: JSON_META_VERSION,
+            verdict: status.verdict.as_str(),
+            time: status.user_time.as_micros(),
+            time_sys: status.system_time.as_micros(),
+            time_wall: status.wall_time.as_micros(),
+            mem: status.max_rss,
+            mem_cgroup: status.cgroup_max_memory,
+            minor_faults: status.rusage.minor_faults,
+            major_faults: status.rusage.major_faults,
+            voluntary_context_switches: status.rusage.voluntary_context_switches,
+            involuntary_context_switches: status.rusage.involuntary_context_switches,
+            block_input_operations: status.rusage.block_input_operations,
+            block_output_operations: status.rusage.block_output_operations,
+            status: exit_status,
+            signal,
+            syscall,
+            killed_by: status.killed_by.map(|killed_by| killed_by.as_str()),
+            pids_limit_exceeded: status.pids_limit_exceeded,
+            oom_killed: status.oom_killed,
+            extracted: status
+                .extracted
+                .iter()
+                .map(|extracted| JsonExtractedFile {
+                    path: extracted.path.clone(),
+                    size: extracted.size,
+                    error: extracted.error.clone(),
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Returns the name of the syscall, or `#` followed by its number if it is not known.
+fn syscall_name(syscall: i32) -> Result<String> {
+    Ok(syscalls::Sysno::new(syscall.try_into()?)
+        .map_or_else(|| format!("#{}", syscall), |s| String::from(s.name())))
+}
+
+pub(crate) fn write_meta_file<P>(
+    meta: P,
+    meta_format: MetaFormat,
+    status: &JailResult,
+) -> Result<()>
+where
+    P: Debug + AsRef<Path>,
+{
+    let mut meta_file = File::create(&meta).with_context(|| anyhow!("create {:?}", &meta))?;
+
+    if meta_format == MetaFormat::Json {
+        serde_json::to_writer(&mut meta_file, &JsonMeta::new(status)?)
+            .with_context(|| anyhow!("write {:?}", meta))?;
+        meta_file
+            .write_all(b"\n")
+            .with_context(|| anyhow!("write {:?}", meta))?;
+        return Ok(());
+    }
+
+    meta_file
+        .write_fmt(format_args!("time:{}\n", status.user_time.as_micros()))
+        .with_context(|| anyhow!("write {:?}", meta))?;
+    meta_file
+        .write_fmt(format_args!(
+            "time-sys:{}\n",
+            status.system_time.as_micros()
+        ))
+        .with_context(|| anyhow!("write {:?}", meta))?;
+    meta_file
+        .write_fmt(format_args!("time-wall:{}\n", status.wall_time.as_micros()))
+        .with_context(|| anyhow!("write {:?}", meta))?;
+    meta_file
+        .write_fmt(format_args!("mem:{}\n", status.max_rss))
+        .with_context(|| anyhow!("write {:?}", meta))?;
+    if let Some(cgroup_max_memory) = status.cgroup_max_memory {
+        meta_file
+            .write_fmt(format_args!("mem-cgroup:{}\n", cgroup_max_memory))
+            .with_context(|| anyhow!("write {:?}", meta))?;
+    }
+    meta_file
+        .write_fmt(format_args!(
+            "minor-faults:{}\nmajor-faults:{}\n",
+            status.rusage.minor_faults, status.rusage.major_faults
+        ))
+        .with_context(|| anyhow!("write {:?}", meta))?;
+    meta_file
+        .write_fmt(format_args!(
+            "voluntary-context-switches:{}\ninvoluntary-context-switches:{}\n",
+            status.rusage.voluntary_context_switches, status.rusage.involuntary_context_switches
+        ))
+        .with_context(|| anyhow!("write {:?}", meta))?;
+    meta_file
+        .write_fmt(format_args!(
+            "block-input-operations:{}\nblock-output-operations:{}\n",
+            status.rusage.block_input_operations, status.rusage.block_output_operations
+        ))
+        .with_context(|| anyhow!("write {:?}", meta))?;
+    match status.status {
+        WaitStatus::Exited(_, status) => meta_file
+            .write_fmt(format_args!("status:{}\n", status))
+            .with_context(|| anyhow!("write {:?}", meta))?,
+        WaitStatus::Signaled(_, signal) => meta_file
+            .write_fmt(format_args!("signal:{}\n", signal.as_str()))
+            .with_context(|| anyhow!("write {:?}", meta))?,
+        WaitStatus::Syscalled(_, syscall) => meta_file
+            .write_fmt(format_args!(
+                "signal:SIGSYS\nsyscall:{}\n",
+                syscall_name(syscall)?
+            ))
+            .with_context(|| anyhow!("write {:?}", meta))?,
+    }
+    meta_file
+        .write_fmt(format_args!("verdict:{}\n", status.verdict.as_str()))
+        .with_context(|| anyhow!("write {:?}", meta))?;
+    if let Some(killed_by) = status.killed_by {
+        meta_file
+            .write_fmt(format_args!("killed-by:{}\n", killed_by.as_str()))
+            .with_context(|| anyhow!("write {:?}", meta))?;
+    }
+    if status.pids_limit_exceeded {
+        meta_file
+            .write_all(b"pids-limit-exceeded:1\n")
+            .with_context(|| anyhow!("write {:?}", meta))?;
+    }
+    if status.oom_killed {
+        meta_file
+            .write_all(b"oom-killed:1\n")
+            .with_context(|| anyhow!("write {:?}", meta))?;
+    }
+    for extracted in &status.extracted {
+        match (extracted.size, &extracted.error) {
+            (Some(size), _) => meta_file
+                .write_fmt(format_args!("extracted:{}:{}\n", extracted.path, size))
+                .with_context(|| anyhow!("write {:?}", meta))?,
+            (None, error) => meta_file
+                .write_fmt(format_args!(
+                    "extract-error:{}:{}\n",
+                    extracted.path,
+                    error
+                        .as_deref()
+                        .unwrap_or("unknown error")
+                        .replace('\n', " ")
+                ))
+                .with_context(|| anyhow!("write {:?}", meta))?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use nix::sys::signal::Signal;
+    use nix::unistd::Pid;
+
+    use crate::jail::meta::{JsonMeta, JSON_META_VERSION};
+    use crate::jail::{ExtractedFile, JailResult, KilledBy, ResourceUsage, Verdict, WaitStatus};
+
+    fn result(status: WaitStatus) -> JailResult {
+        JailResult {
+            status,
+            verdict: Verdict::RuntimeError,
+            user_time: Duration::from_micros(1500),
+            system_time: Duration::from_micros(250),
+            wall_time: Duration::from_micros(3000),
+            max_rss: 4096,
+            cgroup_max_memory: None,
+            rusage: ResourceUsage {
+                minor_faults: 1,
+                major_faults: 2,
+                voluntary_context_switches: 3,
+                involuntary_context_switches: 4,
+                block_input_operations: 5,
+                block_output_operations: 6,
+            },
+            killed_by: None,
+            pids_limit_exceeded: false,
+            oom_killed: false,
+            extracted: vec![],
+        }
+    }
+
+    #[test]
+    fn test_json_meta() -> Result<()> {
+        assert_eq!(
+            serde_json::to_string(&JsonMeta::new(&result(WaitStatus::Exited(
+                Pid::from_raw(2),
+                1
+            )))?)?,
+            r#"{"version":1,"verdict":"RTE","time":1500,"time-sys":250,"time-wall":3000,"mem":4096,"minor-faults":1,"major-faults":2,"voluntary-context-switches":3,"involuntary-context-switches":4,"block-input-operations":5,"block-output-operations":6,"status":1}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&JsonMeta::new(&result(WaitStatus::Syscalled(
+                Pid::from_raw(2),
+                57
+            )))?)?,
+            r#"{"version":1,"verdict":"RTE","time":1500,"time-sys":250,"time-wall":3000,"mem":4096,"minor-faults":1,"major-faults":2,"voluntary-context-switches":3,"involuntary-context-switches":4,"block-input-operations":5,"block-output-operations":6,"signal":"SIGSYS","syscall":"fork"}"#
+        );
+        let mut killed = result(WaitStatus::Signaled(Pid::from_raw(2), Signal::SIGKILL));
+        killed.killed_by = Some(KilledBy::Interactor);
+        killed.pids_limit_exceeded = true;
+        killed.oom_killed = true;
+        killed.cgroup_max_memory = Some(8192);
+        killed.extracted = vec![
+            ExtractedFile {
+                path: String::from("output.txt"),
+                size: Some(3),
+                error: None,
+            },
+            ExtractedFile {
+                path: String::from("missing.txt"),
+                size: None,
+                error: Some(String::from("not found")),
+            },
+        ];
+        assert_eq!(
+            serde_json::to_string(&JsonMeta::new(&killed)?)?,
+            r#"{"version":1,"verdict":"RTE","time":1500,"time-sys":250,"time-wall":3000,"mem":4096,"mem-cgroup":8192,"minor-faults":1,"major-faults":2,"voluntary-context-switches":3,"involuntary-context-switches":4,"block-input-operations":5,"block-output-operations":6,"signal":"SIGKILL","killed-by":"interactor","pids-limit-exceeded":true,"oom-killed":true,"extracted":[{"path":"output.txt","size":3},{"path":"missing.txt","error":"not found"}]}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_meta_schema() -> Result<()> {
+        let schema: serde_json::Value =
+            serde_json::from_str(include_str!("../../schemas/meta.schema.json"))?;
+        let meta = serde_json::to_value(&JsonMeta::new(&result(WaitStatus::Exited(
+            Pid::from_raw(2),
+            0,
+        )))?)?;
+        let properties = schema["properties"]
+            .as_object()
+            .expect("schema has properties");
+        for key in meta.as_object().expect("meta is an object").keys() {
+            assert!(properties.contains_key(key), "{} missing from schema", key);
+        }
+        for key in schema["required"].as_array().expect("schema has required") {
+            assert!(meta.get(key.as_str().unwrap()).is_some(), "{} missing", key);
+        }
+        assert_eq!(
+            schema["properties"]["version"]["const"],
+            serde_json::json!(JSON_META_VERSION)
+        );
+        Ok(())
+    }
+}
@@ -31,20 +31,29 @@
 //!   [`execve(2)`](https://man7.org/linux/man-pages/man2/execve.2.html) to start executing the
 //!   untrusted code.
 
+mod audit;
+mod batch;
 mod cgroups;
 pub(crate) mod child;
 pub(crate) mod child_init;
+mod cpu_pool;
+mod extract;
+mod interactive;
+mod meta;
 mod options;
 pub(crate) mod parent;
+mod serve;
+mod verdict;
 
 use std::fmt::Debug;
 use std::fs::File;
 use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
 use std::os::unix::net::UnixStream;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::time::{Duration, Instant};
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{Context, Result};
 use flexbuffers::FlexbufferSerializer;
 use nix::errno::Errno;
 use nix::sched::CloneFlags;
@@ -56,11 +65,18 @@ use serde::{Deserialize, Serialize};
 
 use crate::args;
 use crate::jail::cgroups::CGroup;
-use crate::sys::{clone3, CloneArgs};
-
-pub use crate::sys::WaitStatus;
+use crate::sys::{clone3, pidfd_send_signal, CloneArgs};
+
+pub use crate::jail::batch::{BatchCase, BatchCommand};
+pub use crate::jail::cgroups::gc_cgroups;
+pub use crate::jail::cpu_pool::{CpuLease, CpuPool};
+pub use crate::jail::interactive::{InteractiveCommand, InteractiveJail, InteractiveJailResult};
+pub use crate::jail::serve::{
+    send_request, serve, ServeArgs, ServeConfig, ServeRequest, ServeResponse,
+};
 /// An alias of WaitidStatus.
 pub use crate::sys::WaitidStatus as JailResult;
+pub use crate::sys::{ExtractedFile, KilledBy, ResourceUsage, Verdict, WaitStatus};
 
 #[derive(Serialize, Deserialize, Debug)]
 struct ParentSetupDoneEvent {}
@@ -74,7 +90,12 @@ struct SendSeccompFDEvent {
 struct SetupCgroupRequest {}
 
 #[derive(Serialize, Deserialize, Debug)]
-struct SetupCgroupResponse {}
+struct SetupCgroupResponse {
+    cpu_stat_available: bool,
+    pids_limit_enforced: bool,
+    memory_limit_enforced: bool,
+    killer_available: bool,
+}
 
 fn write_message<T: Serialize>(writer: &mut UnixStream, message: T) -> Result<()> {
     let mut s = FlexbufferSerializer::new();
@@ -101,18 +122,75 @@ fn read_message<T: DeserializeOwned>(reader: &mut UnixStream) -> Result<T> {
 /// A builder for [`Jail`].
 pub struct Command {
     args: args::Args,
+    stdin: Option<File>,
+    stdout: Option<File>,
+    stderr: Option<File>,
+    cpu_pool: Option<CpuPool>,
 }
 
 impl Command {
     /// Constructs a new `Command` for spawning a [`Jail`].
     pub fn new(args: args::Args) -> Command {
-        Command { args: args }
+        Command {
+            args,
+            stdin: None,
+            stdout: None,
+            stderr: None,
+            cpu_pool: None,
+        }
+    }
+
+    /// Redirects the sandboxed process' stdin to `file` (e.g. the read end of a pipe) instead of
+    /// the path in [`Args::stdin`](args::Args::stdin).
+    pub fn stdin(mut self, file: File) -> Command {
+        self.stdin = Some(file);
+        self
+    }
+
+    /// Redirects the sandboxed process' stdout to `file` (e.g. the write end of a pipe) instead of
+    /// the path in [`Args::stdout`](args::Args::stdout).
+    pub fn stdout(mut self, file: File) -> Command {
+        self.stdout = Some(file);
+        self
+    }
+
+    /// Redirects the sandboxed process' stderr to `file` instead of the path in
+    /// [`Args::stderr`](args::Args::stderr).
+    pub fn stderr(mut self, file: File) -> Command {
+        self.stderr = Some(file);
+        self
+    }
+
+    /// Pins the sandboxed process to a core leased from `pool` instead of the one in
+    /// [`Args::cpu`](args::Args::cpu). If all the cores are in use, [`spawn`](Command::spawn())
+    /// waits until one of them is returned to the pool. The core is returned once the [`Jail`] has
+    /// been waited for.
+    pub fn cpu_pool(mut self, pool: CpuPool) -> Command {
+        self.cpu_pool = Some(pool);
+        self
     }
 
     /// Executes the [`Jail`] as a child, sandboxed process, returning a handle to it.
     pub fn spawn(self) -> Result<Jail> {
-        let jail_options = options::JailOptions::new(self.args).context("create jail options")?;
-        Jail::new(jail_options)
+        let mut jail_options =
+            options::JailOptions::new(self.args).context("create jail options")?;
+        if let Some(stdin) = &self.stdin {
+            jail_options.set_stdin_fd(stdin.as_raw_fd());
+        }
+        if let Some(stdout) = &self.stdout {
+            jail_options.set_stdout_fd(stdout.as_raw_fd());
+        }
+        if let Some(stderr) = &self.stderr {
+            jail_options.set_stderr_fd(stderr.as_raw_fd());
+        }
+        let cpu_lease = self.cpu_pool.as_ref().map(|pool| pool.acquire());
+        if let Some(cpu_lease) = &cpu_lease {
+            jail_options.cpu = Some(cpu_lease.cpu());
+        }
+        // The files are closed in this process once the sandboxed process has been spawned.
+        let mut jail = Jail::new(jail_options)?;
+        jail.cpu_lease = cpu_lease;
+        Ok(jail)
     }
 }
 
@@ -141,8 +219,18 @@ pub struct Jail {
     child: Pid,
     child_start: Instant,
     meta: Option<PathBuf>,
+    meta_format: args::MetaFormat,
     parent_sock: UnixStream,
     cgroups: Vec<CGroup>,
+    jailed_pidfd: Option<File>,
+    killed_by: Option<KilledBy>,
+    cpu_lease: Option<CpuLease>,
+    extracts: Vec<extract::Extract>,
+    extract_size_limit: u64,
+    audit: Option<PathBuf>,
+    time_limit: Option<Duration>,
+    requested_memory_limit: Option<u64>,
+    output_limit: Option<u64>,
 }
 
 impl Jail {
@@ -184,33 +272,71 @@ impl Jail {
         }
 
         std::mem::drop(parent_jail_sock);
-        let cgroups = match parent::setup_child(&mut parent_sock, child, &jail_options) {
-            Ok(cgroups) => cgroups,
-            Err(err) => {
-                log::error!("setup child failed: {:#}", err);
-
-                // Forcibly kill the child, but still return so that the caller can still call
-                // wait().
-                kill(child, Signal::SIGKILL).context("kill child")?;
-                return Ok(Jail {
-                    child: child,
-                    child_start: child_start,
-                    meta: jail_options.meta,
-                    parent_sock: parent_sock,
-                    cgroups: vec![],
-                });
-            }
-        };
+        let (cgroups, jailed_pidfd) =
+            match parent::setup_child(&mut parent_sock, child, &jail_options) {
+                Ok(result) => result,
+                Err(err) => {
+                    log::error!("setup child failed: {:#}", err);
+
+                    // Forcibly kill the child, but still return so that the caller can still call
+                    // wait().
+                    kill(child, Signal::SIGKILL).context("kill child")?;
+                    return Ok(Jail {
+                        child,
+                        child_start,
+                        meta: jail_options.meta,
+                        meta_format: jail_options.meta_format,
+                        parent_sock,
+                        cgroups: vec![],
+                        jailed_pidfd: None,
+                        killed_by: None,
+                        cpu_lease: None,
+                        extracts: vec![],
+                        extract_size_limit: 0,
+                        audit: None,
+                        time_limit: jail_options.time_limit,
+                        requested_memory_limit: jail_options.requested_memory_limit,
+                        output_limit: jail_options.output_limit,
+                    });
+                }
+            };
 
         Ok(Jail {
             child: child,
             child_start: child_start,
             meta: jail_options.meta,
+            meta_format: jail_options.meta_format,
             parent_sock: parent_sock,
             cgroups: cgroups,
+            jailed_pidfd,
+            killed_by: None,
+            cpu_lease: None,
+            extracts: jail_options.extracts,
+            extract_size_limit: jail_options.extract_size_limit,
+            audit: jail_options.audit,
+            time_limit: jail_options.time_limit,
+            requested_memory_limit: jail_options.requested_memory_limit,
+            output_limit: jail_options.output_limit,
         })
     }
 
+    /// Forcibly terminates the sandboxed process. If this causes the process to terminate, its
+    /// [`JailResult`] will have `killed_by` as the reason.
+    ///
+    /// This can be called before [`wait`](Jail::wait()), and it is not an error if the process had
+    /// already exited.
+    pub fn kill(&mut self, killed_by: KilledBy) -> Result<()> {
+        self.killed_by = Some(killed_by);
+        match &self.jailed_pidfd {
+            Some(jailed_pidfd) => match pidfd_send_signal(jailed_pidfd, Signal::SIGKILL) {
+                Err(err) if err.downcast_ref::<Errno>() == Some(&Errno::ESRCH) => Ok(()),
+                result => result,
+            },
+            // Without the jailed process' pidfd, the whole container needs to go.
+            None => kill(self.child, Signal::SIGKILL).context("kill child"),
+        }
+    }
+
     /// Waits for the sandboxed process to exit completely, returning information about resource
     /// usage and exit status of the process.
     ///
@@ -218,20 +344,54 @@ impl Jail {
     pub fn wait(mut self) -> Result<JailResult> {
         // Even if we don't get a result back, proceed so that we can wait on the child. This
         // prevents the sandbox from becoming a zombie.
-        let status = match read_message::<JailResult>(&mut self.parent_sock) {
+        let mut status = match read_message::<JailResult>(&mut self.parent_sock) {
             Err(err) => {
                 log::error!("read waitid status message: {:#}", err);
                 let _ = kill(self.child, Signal::SIGKILL);
                 JailResult {
                     status: WaitStatus::Signaled(self.child, Signal::SIGKILL),
+                    verdict: Verdict::JudgeError,
                     user_time: Duration::ZERO,
                     system_time: Duration::ZERO,
                     wall_time: Instant::now().duration_since(self.child_start),
                     max_rss: 0,
+                    cgroup_max_memory: None,
+                    rusage: ResourceUsage::default(),
+                    killed_by: None,
+                    pids_limit_exceeded: false,
+                    oom_killed: false,
+                    extracted: vec![],
                 }
             }
-            Ok(status) => status,
+            Ok(mut status) => {
+                if let Some(audit) = &self.audit {
+                    match read_message::<audit::SyscallAudit>(&mut self.parent_sock) {
+                        Ok(syscalls) => {
+                            if let Err(err) = syscalls.write(audit) {
+                                log::error!("write syscall audit: {:#}", err);
+                            }
+                        }
+                        Err(err) => log::error!("read syscall audit: {:#}", err),
+                    }
+                }
+                if !self.extracts.is_empty() {
+                    match extract::receive_extracted_files(
+                        &mut self.parent_sock,
+                        &self.extracts,
+                        self.extract_size_limit,
+                    ) {
+                        Ok(extracted) => status.extracted = extracted,
+                        Err(err) => log::error!("receive extracted files: {:#}", err),
+                    }
+                }
+                status
+            }
         };
+        if let (Some(killed_by), WaitStatus::Signaled(_, Signal::SIGKILL)) =
+            (self.killed_by, &status.status)
+        {
+            status.killed_by = Some(killed_by);
+        }
 
         loop {
             match waitpid(self.child, None) {
@@ -247,64 +407,53 @@ impl Jail {
             }
         }
 
+        for cgroup in &self.cgroups {
+            match cgroup.pids_limit_exceeded() {
+                Ok(exceeded) => status.pids_limit_exceeded |= exceeded,
+                Err(err) => log::error!("read cgroup pids events: {:#}", err),
+            }
+            match cgroup.oom_killed() {
+                Ok(oom_killed) => status.oom_killed |= oom_killed,
+                Err(err) => log::error!("read cgroup memory events: {:#}", err),
+            }
+            match cgroup.memory_peak() {
+                Ok(Some(peak)) => {
+                    status.cgroup_max_memory = Some(status.cgroup_max_memory.unwrap_or(0).max(peak))
+                }
+                Ok(None) => {}
+                Err(err) => log::error!("read cgroup memory peak: {:#}", err),
+            }
+        }
+        // The sandboxed init classified the result without knowing whether the process was killed
+        // by this process, or what the cgroups recorded.
+        if status.verdict != Verdict::JudgeError {
+            status.verdict = verdict::classify(
+                &status,
+                self.time_limit,
+                self.requested_memory_limit,
+                self.output_limit,
+            );
+        }
+
         // This is here just to make the dead code detector to avoid complaining about the cgroups.
         // This way the directories will be deleted here once the child has exited.
         std::mem::drop(self.cgroups);
 
         if let Some(meta) = &self.meta {
-            if let Err(err) = Jail::wait_write_meta_file(&meta, &status) {
+            if let Err(err) = meta::write_meta_file(&meta, self.meta_format, &status) {
                 log::error!("write meta file: {:#}", err);
             }
         }
 
         Ok(status)
     }
-
-    fn wait_write_meta_file<P>(meta: P, status: &JailResult) -> Result<()>
-    where
-        P: Debug + AsRef<Path>,
-    {
-        let mut meta_file = File::create(&meta).with_context(|| anyhow!("create {:?}", &meta))?;
-
-        meta_file
-            .write_fmt(format_args!("time:{}\n", status.user_time.as_micros()))
-            .with_context(|| anyhow!("write {:?}", meta))?;
-        meta_file
-            .write_fmt(format_args!(
-                "time-sys:{}\n",
-                status.system_time.as_micros()
-            ))
-            .with_context(|| anyhow!("write {:?}", meta))?;
-        meta_file
-            .write_fmt(format_args!("time-wall:{}\n", status.wall_time.as_micros()))
-            .with_context(|| anyhow!("write {:?}", meta))?;
-        meta_file
-            .write_fmt(format_args!("mem:{}\n", status.max_rss))
-            .with_context(|| anyhow!("write {:?}", meta))?;
-        match status.status {
-            WaitStatus::Exited(_, status) => meta_file
-                .write_fmt(format_args!("status:{}\n", status))
-                .with_context(|| anyhow!("write {:?}", meta))?,
-            WaitStatus::Signaled(_, signal) => meta_file
-                .write_fmt(format_args!("signal:{}\n", signal.as_str()))
-                .with_context(|| anyhow!("write {:?}", meta))?,
-            WaitStatus::Syscalled(_, syscall) => meta_file
-                .write_fmt(format_args!(
-                    "signal:SIGSYS\nsyscall:{}\n",
-                    syscalls::Sysno::new(syscall.try_into()?)
-                        .map_or_else(|| format!("#{}", syscall), |s| String::from(s.name()))
-                ))
-                .with_context(|| anyhow!("write {:?}", meta))?,
-        }
-        Ok(())
-    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::ffi::CString;
     use std::fs::{create_dir, read_to_string, write, File};
-    use std::path::PathBuf;
+    use std::path::{Path, PathBuf};
     use std::process::Command;
     use std::time::Duration;
 
@@ -315,10 +464,11 @@ mod tests {
     use once_cell::sync::Lazy;
     use tempdir::TempDir;
 
+    use crate::args::MetaFormat;
     use crate::jail::options::{JailOptions, MountArgs, Stdio};
-    use crate::jail::{Jail, JailResult, WaitStatus};
+    use crate::jail::{Jail, JailResult, KilledBy, Verdict, WaitStatus};
 
-    fn init() {
+    pub(crate) fn init() {
         let _ = env_logger::builder().is_test(true).try_init();
     }
 
@@ -328,6 +478,7 @@ mod tests {
         stdout: Option<&'static str>,
         stderr: Option<&'static str>,
         status: WaitStatus,
+        audit: bool,
     }
 
     impl Default for TestCase {
@@ -338,6 +489,7 @@ mod tests {
                 stdout: None,
                 stderr: None,
                 status: WaitStatus::Exited(Pid::from_raw(2), 0),
+                audit: false,
             }
         }
     }
@@ -355,24 +507,24 @@ mod tests {
             .unwrap()
     });
 
-    fn run_test_case(test_case: TestCase) -> Result<JailResult> {
-        let tmp_dir = TempDir::new(&test_case.widget)
-            .with_context(|| anyhow!("TempDir::new({})", &test_case.widget))?;
-
-        let stdin_path = tmp_dir.path().join("stdin");
-        write(&stdin_path, test_case.stdin.as_bytes())
-            .with_context(|| anyhow!("write({:?}, {})", &stdin_path, &test_case.stdin))?;
-        let stdout_path = tmp_dir.path().join("stdout");
+    /// Returns the options to run `widget` in a sandbox whose stdin, stdout and stderr are the
+    /// files of the same name in `tmp_dir`.
+    pub(crate) fn test_options(tmp_dir: &Path, widget: &str, stdin: &str) -> Result<JailOptions> {
+        let stdin_path = tmp_dir.join("stdin");
+        write(&stdin_path, stdin.as_bytes())
+            .with_context(|| anyhow!("write({:?}, {})", &stdin_path, stdin))?;
+        let stdout_path = tmp_dir.join("stdout");
         File::create(&stdout_path).with_context(|| anyhow!("File::create({:?})", &stdout_path))?;
-        let stderr_path = tmp_dir.path().join("stderr");
+        let stderr_path = tmp_dir.join("stderr");
         File::create(&stderr_path).with_context(|| anyhow!("File::create({:?})", &stderr_path))?;
 
-        let rootfs_path = tmp_dir.path().join("rootfs");
+        let rootfs_path = tmp_dir.join("rootfs");
         create_dir(&rootfs_path).with_context(|| anyhow!("create_dir({:?})", &rootfs_path))?;
 
-        let options = JailOptions {
+        Ok(JailOptions {
             disable_sandboxing: false,
             homedir: PathBuf::from("/home"),
+            home_overlay: None,
             rootfs: rootfs_path.clone(),
             cgroup_path: None,
             mounts: vec![
@@ -398,16 +550,16 @@ mod tests {
                     data: Some(String::from("size=4096,mode=555")),
                 },
                 MountArgs {
-                    source: Some(PathBuf::from(tmp_dir.path())),
+                    source: Some(PathBuf::from(tmp_dir)),
                     target: rootfs_path.join("mnt/stdio"),
                     fstype: None,
-                    flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY | MsFlags::MS_REC,
+                    flags: MsFlags::MS_BIND | MsFlags::MS_REC,
                     data: None,
                 },
             ],
             args: vec![
                 CString::new((*TEST_HELPER_PATH).to_str().ok_or_else(|| anyhow!("path is not unicode"))?)?,
-                CString::new(format!("--widget={}", test_case.widget))?,
+                CString::new(format!("--widget={}", widget))?,
             ],
             env: vec![],
             // allows everything _except_ `mount(2)`.
@@ -415,6 +567,7 @@ mod tests {
             seccomp_bpf_filter_sigsys_contents: base64::decode("IAAAAAQAAAAVAAEAPgAAwAYAAAAAAAAAIAAAAAAAAAAVAAIBpQAAAAYAAAAAAP9/BgAAAAAA/38GAAAAAADAfw==")?,
             seccomp_profile_name: String::from("test"),
             meta: None,
+            meta_format: MetaFormat::Text,
 
             stdin: Stdio::Mounted(stdin_path.clone()),
             stdout: Stdio::Mounted(stdout_path.clone()),
@@ -424,10 +577,30 @@ mod tests {
             wall_time_limit: Duration::from_secs(2),
             output_limit: Some(16 * 1024),
             memory_limit: Some(32 * 1024 * 1024),
+            pids_limit: None,
+            requested_memory_limit: Some(32 * 1024 * 1024),
             use_cgroups_for_memory_limit: false,
+            require_cgroups_for_memory_limit: false,
+            cgroup_memory_limit: Some(32 * 1024 * 1024),
             vm_memory_size_in_bytes: 0u64,
             allow_sigsys_fallback: false,
-        };
+            cpu: None,
+            extracts: vec![],
+            extract_size_limit: 0,
+            audit: None,
+        })
+    }
+
+    fn run_test_case(test_case: TestCase) -> Result<JailResult> {
+        let tmp_dir = TempDir::new(test_case.widget)
+            .with_context(|| anyhow!("TempDir::new({})", &test_case.widget))?;
+        let stdout_path = tmp_dir.path().join("stdout");
+        let stderr_path = tmp_dir.path().join("stderr");
+
+        let mut options = test_options(tmp_dir.path(), test_case.widget, test_case.stdin)?;
+        if test_case.audit {
+            options.audit = Some(tmp_dir.path().join("audit"));
+        }
 
         let jail = Jail::new(options)?;
 
@@ -442,6 +615,19 @@ mod tests {
             let stderr = read_to_string(&stderr_path)?;
             assert_eq!(expected_stderr, &stderr);
         }
+        if test_case.audit {
+            let policy = read_to_string(tmp_dir.path().join("audit.policy"))?;
+            for syscall in ["execve", "mount", "exit_group"] {
+                assert!(
+                    policy.contains(&format!("\n{}: allow\n", syscall)),
+                    "{} not allowed in {}",
+                    syscall,
+                    policy
+                );
+            }
+            let frequency = read_to_string(tmp_dir.path().join("audit.frequency"))?;
+            assert!(frequency.contains("\nmount: 1\n"), "{}", frequency);
+        }
 
         Ok(result)
     }
@@ -517,6 +703,21 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_audit() -> Result<()> {
+        init();
+        run_test_case(TestCase {
+            widget: "syscall",
+            stdin: "",
+            // mount(2) is allowed to continue, and fails because the process has no capabilities.
+            status: WaitStatus::Exited(Pid::from_raw(2), 1),
+            audit: true,
+            ..TestCase::default()
+        })?;
+
+        Ok(())
+    }
+
     #[test]
     fn test_sigxfsz() -> Result<()> {
         init();
@@ -544,6 +745,31 @@ mod tests {
         // This is proactively killed by the CPU time limit.
         assert!(result.wall_time >= Duration::from_secs(1));
         assert!(result.wall_time <= Duration::from_secs(2));
+        assert_eq!(result.verdict, Verdict::TimeLimitExceeded);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pids_limit_fallback() -> Result<()> {
+        init();
+        // Without a cgroup, the pids limit is enforced through RLIMIT_NPROC, which also counts the
+        // sandboxed init.
+        let tmp_dir = TempDir::new("limits")?;
+        let mut options = test_options(tmp_dir.path(), "limits", "")?;
+        options.pids_limit = Some(16);
+        let result = Jail::new(options)?.wait()?;
+        assert_eq!(result.status, WaitStatus::Exited(Pid::from_raw(2), 0));
+        assert!(!result.pids_limit_exceeded);
+
+        let limits = read_to_string(tmp_dir.path().join("stdout"))?;
+        let max_processes: Vec<&str> = limits
+            .lines()
+            .find(|line| line.starts_with("Max processes"))
+            .ok_or_else(|| anyhow!("no process limit in {}", limits))?
+            .split_whitespace()
+            .collect();
+        assert_eq!(max_processes[2..4], ["17", "17"], "{}", limits);
 
         Ok(())
     }
@@ -561,6 +787,7 @@ mod tests {
 
         // This does not use any CPU (it's only sleeping), so it's killed by the wall-time limit.
         assert!(result.wall_time >= Duration::from_secs(2));
+        assert_eq!(result.killed_by, Some(KilledBy::WallTimeLimit));
 
         Ok(())
     }
@@ -2,7 +2,7 @@ use std::ffi::CString;
 use std::fs::{canonicalize, File};
 use std::io::Read;
 use std::os::unix::io::RawFd;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 use std::time::Duration;
 
 use anyhow::{anyhow, bail, Context, Result};
@@ -10,32 +10,11 @@ use itertools::Itertools;
 use nix::mount::MsFlags;
 
 use crate::args;
+use crate::jail::extract::Extract;
+use crate::languages::{LanguageRegistry, RecipeContext};
+use crate::seccomp::compile_file_cached;
 
-const DEFAULT_EXTRA_MEMORY_SIZE_IN_BYTES: u64 = 16 * 1024 * 1024;
-const RUBY_EXTRA_MEMORY_SIZE_IN_BYTES: u64 = 56 * 1024 * 1024;
-const GO_EXTRA_MEMORY_SIZE_IN_BYTES: u64 = 512 * 1024 * 1024;
-const NODE_EXTRA_MEMORY_SIZE_IN_BYTES: u64 = 89 * 1024 * 1024;
-
-// These are obtained by running an "empty" and measuring
-// its memory consumption, as reported by omegajail.
-const JAVA_VM_MEMORY_SIZE_IN_BYTES: u64 = 47 * 1024 * 1024;
-const CLR_VM_MEMORY_SIZE_IN_BYTES: u64 = 20 * 1024 * 1024;
-const RUBY_VM_MEMORY_SIZE_IN_BYTES: u64 = 12 * 1024 * 1024;
-const NODE_VM_MEMORY_SIZE_IN_BYTES: u64 = 31 * 1024 * 1024;
-
-// This is the result of executing the following Java code:
-//
-// public class Main {
-//   public static void main(String[] args) {
-//     System.out.println(
-//         16 * 1024 * 1024 +
-//         Runtime.getRuntime().totalMemory() -
-//         Runtime.getRuntime().freeMemory()
-//     );
-//   }
-// }
-const JAVA_MIN_HEAP_SIZE_IN_BYTES: u64 = 18 * 1024 * 1024;
-
+#[derive(Debug, Clone)]
 pub(crate) enum Stdio {
     Mounted(PathBuf),
     DevNull(PathBuf),
@@ -51,9 +30,22 @@ pub(crate) struct MountArgs {
     pub data: Option<String>,
 }
 
+/// An overlay mounted as /home, which makes it writable without modifying the host's directory.
+#[derive(Debug, Clone)]
+pub(crate) struct HomeOverlay {
+    /// The host's directory, which is the read-only lower layer.
+    pub lowerdir: PathBuf,
+    /// The size limit of the tmpfs that holds the upper layer, in bytes.
+    pub size: u64,
+    /// The inode limit of the tmpfs that holds the upper layer.
+    pub inodes: u64,
+}
+
+#[derive(Clone)]
 pub(crate) struct JailOptions {
     pub disable_sandboxing: bool,
     pub homedir: PathBuf,
+    pub home_overlay: Option<HomeOverlay>,
     pub rootfs: PathBuf,
     pub cgroup_path: Option<PathBuf>,
     pub mounts: Vec<MountArgs>,
@@ -63,6 +55,7 @@ pub(crate) struct JailOptions {
     pub seccomp_bpf_filter_sigsys_contents: Vec<u8>,
     pub seccomp_profile_name: String,
     pub meta: Option<PathBuf>,
+    pub meta_format: args::MetaFormat,
 
     pub stdin: Stdio,
     pub stdout: Stdio,
@@ -72,9 +65,20 @@ pub(crate) struct JailOptions {
     pub wall_time_limit: Duration,
     pub output_limit: Option<u64>,
     pub memory_limit: Option<u64>,
+    pub pids_limit: Option<u64>,
+    pub requested_memory_limit: Option<u64>,
     pub use_cgroups_for_memory_limit: bool,
+    /// Whether the program must not run if the memory cgroup cannot enforce the memory limit.
+    pub require_cgroups_for_memory_limit: bool,
+    pub cgroup_memory_limit: Option<u64>,
     pub vm_memory_size_in_bytes: u64,
     pub allow_sigsys_fallback: bool,
+    pub cpu: Option<usize>,
+    pub extracts: Vec<Extract>,
+    pub extract_size_limit: u64,
+    /// Where the draft policy is written in audit mode, without the extension. The seccomp
+    /// filters are not used in audit mode.
+    pub audit: Option<PathBuf>,
 }
 
 impl JailOptions {
@@ -88,20 +92,38 @@ impl JailOptions {
         } else {
             root.join("root")
         };
-        mounts.push(MountArgs {
-            source: Some(PathBuf::from(
-                canonicalize(&args.homedir)
-                    .with_context(|| format!("canonicalize({})", &args.homedir))?,
-            )),
-            target: rootfs.join("home"),
-            fstype: None,
-            flags: if args.homedir_writable {
-                MsFlags::MS_BIND
-            } else {
-                MsFlags::MS_BIND | MsFlags::MS_RDONLY
-            },
-            data: None,
-        });
+        let homedir = PathBuf::from(
+            canonicalize(&args.homedir)
+                .with_context(|| format!("canonicalize({})", &args.homedir))?,
+        );
+        let home_overlay = if args.homedir_overlay {
+            if args.disable_sandboxing {
+                bail!("--homedir-overlay cannot be used with --disable-sandboxing");
+            }
+            // The overlay options are separated by commas, and the lower layers by colons.
+            if homedir.to_string_lossy().contains(&[',', ':'][..]) {
+                bail!("--homedir {:?} cannot be used as an overlay", &homedir);
+            }
+            // The overlay is mounted as /home before any of the other mounts.
+            Some(HomeOverlay {
+                lowerdir: homedir,
+                size: args.homedir_overlay_size,
+                inodes: args.homedir_overlay_inodes,
+            })
+        } else {
+            mounts.push(MountArgs {
+                source: Some(homedir),
+                target: rootfs.join("home"),
+                fstype: None,
+                flags: if args.homedir_writable {
+                    MsFlags::MS_BIND
+                } else {
+                    MsFlags::MS_BIND | MsFlags::MS_RDONLY
+                },
+                data: None,
+            });
+            None
+        };
         mounts.push(MountArgs {
             source: None,
             target: rootfs.join("proc"),
@@ -116,665 +138,101 @@ impl JailOptions {
             flags: MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
             data: Some(String::from("size=4096,mode=555")),
         });
+        if args.tmp_size != 0 {
+            mounts.push(tmpfs_mount(
+                &rootfs,
+                "/tmp",
+                args.tmp_size,
+                args.tmp_inodes,
+                args.tmp_exec,
+            )?);
+        }
         // Create the stdout / stderr files if needed.
-        let stdin = if let Some(stdin) = &args.stdin {
-            File::open(stdin).with_context(|| format!("open stdin {}", &stdin))?;
-            let source = PathBuf::from(
-                canonicalize(&stdin).with_context(|| format!("canonicalize({})", &stdin))?,
-            );
-            mounts.push(MountArgs {
-                source: Some(source.clone()),
-                target: rootfs.join("mnt/stdio/stdin"),
-                fstype: None,
-                flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
-                data: None,
-            });
-
-            Stdio::Mounted(source)
-        } else if unsafe { libc::isatty(libc::STDIN_FILENO) == 0 } {
-            let source = rootfs.join("dev/null");
-            mounts.push(MountArgs {
-                source: Some(source.clone()),
-                target: rootfs.join("mnt/stdio/stdin"),
-                fstype: None,
-                flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
-                data: None,
-            });
+        let stdin = setup_stdin(&rootfs, args.stdin.as_deref(), &mut mounts)?;
+        let stdout = setup_stdout(&rootfs, args.stdout.as_deref(), &mut mounts)?;
+        let stderr = setup_stderr(&rootfs, args.stderr.as_deref(), &mut mounts)?;
 
-            Stdio::DevNull(source)
+        let registry = LanguageRegistry::load(&root).context("load language registry")?;
+        let (recipe, target, compile_sources) = if let Some(lang) = &args.compile {
+            (
+                registry
+                    .resolve(lang)?
+                    .compile
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("language {} cannot be compiled", lang))?,
+                &args.compile_target,
+                args.compile_source
+                    .as_deref()
+                    .ok_or(anyhow!("--compile-source missing"))?,
+            )
+        } else if let Some(lang) = &args.run {
+            (
+                registry
+                    .resolve(lang)?
+                    .run
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("language {} cannot be run", lang))?,
+                &args.run_target,
+                &[][..],
+            )
         } else {
-            Stdio::FileDescriptor(libc::STDIN_FILENO)
+            bail!("one of --compile or --run must be provided");
         };
-        let stdout = if let Some(stdout) = &args.stdout {
-            File::create(stdout).with_context(|| format!("create stdout {}", &stdout))?;
-            let source = PathBuf::from(
-                canonicalize(&stdout).with_context(|| format!("canonicalize({})", &stdout))?,
-            );
-            mounts.push(MountArgs {
-                source: Some(source.clone()),
-                target: rootfs.join("mnt/stdio/stdout"),
-                fstype: None,
-                flags: MsFlags::MS_BIND,
-                data: None,
-            });
-
-            Stdio::Mounted(source)
-        } else if unsafe { libc::isatty(libc::STDOUT_FILENO) == 0 } {
-            let source = rootfs.join("dev/null");
+        for mount in &recipe.mounts {
             mounts.push(MountArgs {
-                source: Some(source.clone()),
-                target: rootfs.join("mnt/stdio/stdout"),
+                source: Some(root.join(&mount.source)),
+                target: rootfs.join(mount.target.strip_prefix("/")?),
                 fstype: None,
-                flags: MsFlags::MS_BIND,
+                flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
                 data: None,
             });
+        }
+        let mut execve_args = recipe
+            .expand_args(&RecipeContext {
+                target,
+                sources: compile_sources,
+                memory_limit: args.memory_limit,
+            })
+            .context("expand language arguments")?;
+        let mut env: Vec<String> = vec![
+            String::from("HOME=/home"),
+            String::from("LANG=en_US.UTF-8"),
+            String::from("PATH=/usr/bin"),
+        ];
+        env.extend(recipe.env.iter().cloned());
+        let seccomp_profile_name = recipe.seccomp_profile.clone();
 
-            Stdio::DevNull(source)
-        } else {
-            Stdio::FileDescriptor(libc::STDOUT_FILENO)
-        };
-        let stderr = if let Some(stderr) = &args.stderr {
-            File::options()
-                .append(true)
-                .create(true)
-                .open(stderr)
-                .with_context(|| format!("create stderr {}", &stderr))?;
-            let source = PathBuf::from(
-                canonicalize(&stderr).with_context(|| format!("canonicalize({})", &stderr))?,
+        for bind in &args.bind {
+            mounts.push(
+                parse_mount_spec(&rootfs, bind)
+                    .with_context(|| anyhow!("invalid bind description: {:?}", bind))?,
             );
-            mounts.push(MountArgs {
-                source: Some(source.clone()),
-                target: rootfs.join("mnt/stdio/stderr"),
-                fstype: None,
-                flags: MsFlags::MS_BIND,
-                data: None,
-            });
-
-            Stdio::Mounted(source)
-        } else if unsafe { libc::isatty(libc::STDERR_FILENO) == 0 } {
-            let source = rootfs.join("dev/null");
-            mounts.push(MountArgs {
-                source: Some(source.clone()),
-                target: rootfs.join("mnt/stdio/stderr"),
-                fstype: None,
-                flags: MsFlags::MS_BIND,
-                data: None,
-            });
-
-            Stdio::DevNull(source)
-        } else {
-            Stdio::FileDescriptor(libc::STDERR_FILENO)
-        };
-
-        let mut execve_args = Vec::<String>::new();
-        let mut env: Vec<&str> = vec!["HOME=/home", "LANG=en_US.UTF-8", "PATH=/usr/bin"];
-        let mut seccomp_profile_name = String::new();
-        let mut extra_memory_size_in_bytes = DEFAULT_EXTRA_MEMORY_SIZE_IN_BYTES;
-        let mut vm_memory_size_in_bytes = 0u64;
-        let mut use_cgroups_for_memory_limit: bool = false;
-
-        if let Some(lang) = args.compile {
-            let compile_sources = &args
-                .compile_source
-                .ok_or(anyhow!("--compile-source missing"))?;
-            match lang {
-                args::Language::C | args::Language::C11GCC => {
-                    seccomp_profile_name = String::from("gcc");
-                    execve_args.extend([
-                        String::from("/usr/bin/gcc-10"),
-                        String::from("-o"),
-                        String::from(args.compile_target),
-                        String::from("-std=c11"),
-                        String::from("-O2"),
-                    ]);
-                    add_sources(&mut execve_args, "-xc", compile_sources);
-                    execve_args.push(String::from("-lm"));
-                }
-                args::Language::C11Clang => {
-                    seccomp_profile_name = String::from("clang");
-                    execve_args.extend([
-                        String::from("/usr/bin/clang-10"),
-                        String::from("-o"),
-                        String::from(args.compile_target),
-                        String::from("-std=c11"),
-                        String::from("-O3"),
-                        String::from("-march=native"),
-                    ]);
-                    add_sources(&mut execve_args, "-xc", compile_sources);
-                    execve_args.push(String::from("-lm"));
-                }
-                args::Language::Cpp03GCC => {
-                    seccomp_profile_name = String::from("gcc");
-                    execve_args.extend([
-                        String::from("/usr/bin/g++-10"),
-                        String::from("-o"),
-                        String::from(args.compile_target),
-                        String::from("-std=c++03"),
-                        String::from("-O2"),
-                    ]);
-                    add_sources(&mut execve_args, "-xc++", compile_sources);
-                    execve_args.push(String::from("-lm"));
-                }
-                args::Language::Cpp03Clang => {
-                    seccomp_profile_name = String::from("clang");
-                    execve_args.extend([
-                        String::from("/usr/bin/clang++-10"),
-                        String::from("-o"),
-                        String::from(args.compile_target),
-                        String::from("-std=c++03"),
-                        String::from("-O2"),
-                    ]);
-                    add_sources(&mut execve_args, "-xc++", compile_sources);
-                    execve_args.push(String::from("-lm"));
-                }
-                args::Language::Cpp | args::Language::Cpp11 | args::Language::Cpp11GCC => {
-                    seccomp_profile_name = String::from("gcc");
-                    execve_args.extend([
-                        String::from("/usr/bin/g++-10"),
-                        String::from("-o"),
-                        String::from(args.compile_target),
-                        String::from("-std=c++11"),
-                        String::from("-O2"),
-                    ]);
-                    add_sources(&mut execve_args, "-xc++", compile_sources);
-                    execve_args.push(String::from("-lm"));
-                }
-                args::Language::Cpp11Clang => {
-                    seccomp_profile_name = String::from("clang");
-                    execve_args.extend([
-                        String::from("/usr/bin/clang++-10"),
-                        String::from("-o"),
-                        String::from(args.compile_target),
-                        String::from("-std=c++11"),
-                        String::from("-O2"),
-                    ]);
-                    add_sources(&mut execve_args, "-xc++", compile_sources);
-                    execve_args.push(String::from("-lm"));
-                }
-                args::Language::Cpp17GCC => {
-                    seccomp_profile_name = String::from("gcc");
-                    execve_args.extend([
-                        String::from("/usr/bin/g++-10"),
-                        String::from("-o"),
-                        String::from(args.compile_target),
-                        String::from("-std=c++17"),
-                        String::from("-O2"),
-                    ]);
-                    add_sources(&mut execve_args, "-xc++", compile_sources);
-                    execve_args.push(String::from("-lm"));
-                }
-                args::Language::Cpp17Clang => {
-                    seccomp_profile_name = String::from("clang");
-                    execve_args.extend([
-                        String::from("/usr/bin/clang++-10"),
-                        String::from("-o"),
-                        String::from(args.compile_target),
-                        String::from("-std=c++17"),
-                        String::from("-O2"),
-                    ]);
-                    add_sources(&mut execve_args, "-xc++", compile_sources);
-                    execve_args.push(String::from("-lm"));
-                }
-                args::Language::Cpp20GCC => {
-                    seccomp_profile_name = String::from("gcc");
-                    execve_args.extend([
-                        String::from("/usr/bin/g++-10"),
-                        String::from("-o"),
-                        String::from(args.compile_target),
-                        String::from("-std=c++20"),
-                        String::from("-O2"),
-                    ]);
-                    add_sources(&mut execve_args, "-xc++", compile_sources);
-                    execve_args.push(String::from("-lm"));
-                }
-                args::Language::Cpp20Clang => {
-                    seccomp_profile_name = String::from("clang");
-                    execve_args.extend([
-                        String::from("/usr/bin/clang++-10"),
-                        String::from("-o"),
-                        String::from(args.compile_target),
-                        String::from("-std=c++20"),
-                        String::from("-O2"),
-                    ]);
-                    add_sources(&mut execve_args, "-xc++", compile_sources);
-                    execve_args.push(String::from("-lm"));
-                }
-                args::Language::Pascal => {
-                    seccomp_profile_name = String::from("fpc");
-                    execve_args.extend([
-                        String::from("/usr/bin/fpc"),
-                        String::from("-Tlinux"),
-                        String::from("-O2"),
-                        String::from("-Mobjfpc"),
-                        String::from("-Sc"),
-                        String::from("-Sh"),
-                        format!("-o{}", args.compile_target),
-                    ]);
-                    execve_args.extend(compile_sources.iter().map(|s| s.clone()));
-                }
-                args::Language::Lua => {
-                    seccomp_profile_name = String::from("lua");
-                    execve_args.extend([
-                        String::from("/usr/bin/luac5.3"),
-                        String::from("-o"),
-                        String::from(args.compile_target),
-                    ]);
-                    execve_args.extend(compile_sources.iter().map(|s| s.clone()));
-                }
-                args::Language::Haskell => {
-                    seccomp_profile_name = String::from("ghc");
-                    mounts.push(MountArgs {
-                        source: Some(root.join("root-hs")),
-                        target: rootfs.join("usr/lib/ghc"),
-                        fstype: None,
-                        flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
-                        data: None,
-                    });
-                    execve_args.extend([
-                        String::from("/usr/lib/ghc/bin/ghc"),
-                        String::from("-B/usr/lib/ghc"),
-                        String::from("-O2"),
-                        String::from("-o"),
-                        String::from(args.compile_target),
-                    ]);
-                    execve_args.extend(compile_sources.iter().map(|s| s.clone()));
-                }
-                args::Language::Java | args::Language::Kotlin => {
-                    seccomp_profile_name = String::from("javac");
-                    mounts.push(MountArgs {
-                        source: Some(root.join("root-java")),
-                        target: rootfs.join("usr/lib/jvm"),
-                        fstype: None,
-                        flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
-                        data: None,
-                    });
-                    mounts.push(MountArgs {
-                        source: Some(root.join("bin")),
-                        target: rootfs.join("var/lib/omegajail/bin"),
-                        fstype: None,
-                        flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
-                        data: None,
-                    });
-                    execve_args.extend([
-                        String::from("/var/lib/omegajail/bin/java-compile"),
-                        String::from(match lang {
-                            args::Language::Java => "--language=java",
-                            args::Language::Kotlin => "--language=kotlin",
-                            _ => panic!("unreachable"),
-                        }),
-                        args.compile_target.clone(),
-                    ]);
-                    execve_args.extend(compile_sources.iter().map(|s| s.clone()));
-                }
-                args::Language::Python2 => {
-                    seccomp_profile_name = String::from("pyc");
-                    mounts.push(MountArgs {
-                        source: Some(root.join("root-python2")),
-                        target: rootfs.join("usr/lib/python2.7"),
-                        fstype: None,
-                        flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
-                        data: None,
-                    });
-                    execve_args.extend([
-                        String::from("/usr/bin/python2.7"),
-                        String::from("-m"),
-                        String::from("py_compile"),
-                    ]);
-                    execve_args.extend(compile_sources.iter().map(|s| s.clone()));
-                }
-                args::Language::Python | args::Language::Python3 => {
-                    seccomp_profile_name = String::from("pyc");
-                    mounts.push(MountArgs {
-                        source: Some(root.join("root-python3")),
-                        target: rootfs.join("opt/python3"),
-                        fstype: None,
-                        flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
-                        data: None,
-                    });
-                    execve_args.extend([
-                        String::from("/usr/bin/python3.9"),
-                        String::from("-m"),
-                        String::from("py_compile"),
-                    ]);
-                    execve_args.extend(compile_sources.iter().map(|s| s.clone()));
-                }
-                args::Language::Ruby => {
-                    seccomp_profile_name = String::from("ruby");
-                    mounts.push(MountArgs {
-                        source: Some(root.join("root-ruby")),
-                        target: rootfs.join("usr/lib/ruby"),
-                        fstype: None,
-                        flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
-                        data: None,
-                    });
-                    execve_args.extend([String::from("/usr/bin/ruby"), String::from("-wc")]);
-                    execve_args.extend(compile_sources.iter().map(|s| s.clone()));
-                }
-                args::Language::Rust => {
-                    seccomp_profile_name = String::from("rustc");
-                    mounts.push(MountArgs {
-                        source: Some(root.join("root-rust")),
-                        target: rootfs.join("opt/rust"),
-                        fstype: None,
-                        flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
-                        data: None,
-                    });
-                    execve_args.extend([
-                        String::from("/opt/rust/cargo/bin/rustc"),
-                        String::from("-O"),
-                        String::from("-o"),
-                        args.compile_target.clone(),
-                    ]);
-                    execve_args.extend(compile_sources.iter().map(|s| s.clone()));
-                }
-                args::Language::Go => {
-                    seccomp_profile_name = String::from("go-build");
-                    mounts.push(MountArgs {
-                        source: Some(root.join("root-go")),
-                        target: rootfs.join("opt/go"),
-                        fstype: None,
-                        flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
-                        data: None,
-                    });
-                    execve_args.extend([
-                        String::from("/opt/go/bin/go"),
-                        String::from("build"),
-                        String::from("-o"),
-                        args.compile_target.clone(),
-                    ]);
-                    execve_args.extend(compile_sources.iter().map(|s| s.clone()));
-                }
-                args::Language::JavaScript => {
-                    seccomp_profile_name = String::from("js");
-                    extra_memory_size_in_bytes = NODE_EXTRA_MEMORY_SIZE_IN_BYTES;
-                    vm_memory_size_in_bytes = NODE_VM_MEMORY_SIZE_IN_BYTES;
-                    mounts.push(MountArgs {
-                        source: Some(root.join("root-js")),
-                        target: rootfs.join("opt/nodejs"),
-                        fstype: None,
-                        flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
-                        data: None,
-                    });
-                    execve_args.extend([
-                        String::from("/opt/nodejs/bin/node"),
-                        String::from("--check"),
-                    ]);
-                    execve_args.extend(compile_sources.iter().map(|s| s.clone()));
-                }
-                args::Language::KarelJava | args::Language::KarelPascal => {
-                    seccomp_profile_name = String::from("js");
-                    extra_memory_size_in_bytes = NODE_EXTRA_MEMORY_SIZE_IN_BYTES;
-                    vm_memory_size_in_bytes = NODE_VM_MEMORY_SIZE_IN_BYTES;
-                    mounts.push(MountArgs {
-                        source: Some(root.join("root-js")),
-                        target: rootfs.join("opt/nodejs"),
-                        fstype: None,
-                        flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
-                        data: None,
-                    });
-                    execve_args.extend([
-                        String::from("/opt/nodejs/bin/node"),
-                        String::from("/opt/nodejs/karel.js"),
-                        String::from("compile"),
-                        String::from(match lang {
-                            args::Language::KarelJava => "java",
-                            args::Language::KarelPascal => "pascal",
-                            _ => panic!("unreachable"),
-                        }),
-                        String::from("-o"),
-                        format!("{}.kx", &args.compile_target),
-                    ]);
-                    execve_args.extend(compile_sources.iter().map(|s| s.clone()));
-                }
-                args::Language::CSharp => {
-                    seccomp_profile_name = String::from("csc");
-                    mounts.push(MountArgs {
-                        source: Some(root.join("root-dotnet")),
-                        target: rootfs.join("usr/share/dotnet"),
-                        fstype: None,
-                        flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
-                        data: None,
-                    });
-                    execve_args.extend([
-                        String::from("/usr/share/dotnet/dotnet"),
-                        String::from("/usr/share/dotnet/sdk/6.0.101/Roslyn/bincore/csc.dll"),
-                        String::from("-noconfig"),
-                        String::from("@/usr/share/dotnet/Release.rsp"),
-                        format!(
-                            "-out:{}.dll",
-                            PathBuf::from(
-                                compile_sources
-                                    .first()
-                                    .ok_or(anyhow!("empty --compile-source"))?
-                            )
-                            .parent()
-                            .context("invalid --compile-source")?
-                            .join(args.compile_target.clone())
-                            .to_str()
-                            .ok_or(anyhow!("could not convert path to string"))?,
-                        ),
-                        String::from("-target:exe"),
-                    ]);
-                    execve_args.extend(compile_sources.iter().map(|s| s.clone()));
-                }
-            }
-        } else if let Some(lang) = args.run {
-            match lang {
-                args::Language::C
-                | args::Language::Cpp
-                | args::Language::C11GCC
-                | args::Language::C11Clang
-                | args::Language::Cpp03GCC
-                | args::Language::Cpp03Clang
-                | args::Language::Cpp11
-                | args::Language::Cpp11GCC
-                | args::Language::Cpp11Clang
-                | args::Language::Cpp17GCC
-                | args::Language::Cpp17Clang
-                | args::Language::Cpp20GCC
-                | args::Language::Cpp20Clang => {
-                    seccomp_profile_name = String::from("cpp");
-                    execve_args.extend([format!("./{}", args.run_target)]);
-                }
-                args::Language::Pascal => {
-                    seccomp_profile_name = String::from("pas");
-                    execve_args.extend([format!("./{}", args.run_target)]);
-                }
-                args::Language::Lua => {
-                    seccomp_profile_name = String::from("lua");
-                    execve_args.extend([
-                        String::from("/usr/bin/lua5.3"),
-                        format!("./{}", args.run_target),
-                    ]);
-                }
-                args::Language::Haskell => {
-                    seccomp_profile_name = String::from("hs");
-                    mounts.push(MountArgs {
-                        source: Some(root.join("root-hs")),
-                        target: rootfs.join("usr/lib/ghc"),
-                        fstype: None,
-                        flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
-                        data: None,
-                    });
-                    execve_args.extend([format!("./{}", args.run_target)]);
-                }
-                args::Language::Java | args::Language::Kotlin => {
-                    vm_memory_size_in_bytes = JAVA_VM_MEMORY_SIZE_IN_BYTES;
-                    extra_memory_size_in_bytes = u64::MAX;
-                    seccomp_profile_name = String::from("java");
-                    mounts.push(MountArgs {
-                        source: Some(root.join("root-java")),
-                        target: rootfs.join("usr/lib/jvm"),
-                        fstype: None,
-                        flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
-                        data: None,
-                    });
-                    execve_args.extend([
-                        String::from("/usr/bin/java"),
-                        String::from("-Xshare:on"),
-                        String::from("-XX:+UnlockExperimentalVMOptions"),
-                        String::from("-XX:+UseSerialGC"),
-                    ]);
-                    if let Some(memory_limit) = &args.memory_limit {
-                        execve_args.extend([format!(
-                            "-Xmx{}",
-                            memory_limit + JAVA_MIN_HEAP_SIZE_IN_BYTES
-                        )]);
-                    }
-                    if lang == args::Language::Kotlin {
-                        execve_args.extend([
-                            format!("-XX:AOTLibrary=/usr/lib/jvm/java.base.so,/usr/lib/jvm/kotlin-stdlib.jar.so,./{}.so",
-                       args.run_target),
-                            String::from("-cp"),
-                            String::from("/usr/lib/jvm/kotlinc/lib/kotlin-stdlib.jar:."),
-                            format!("{}Kt", &args.run_target),
-                        ]);
-                    } else {
-                        execve_args.extend([
-                            format!(
-                                "-XX:AOTLibrary=/usr/lib/jvm/java.base.so,./{}.so",
-                                args.run_target,
-                            ),
-                            args.run_target.clone(),
-                        ]);
-                    }
-                }
-                args::Language::Python2 => {
-                    seccomp_profile_name = String::from("py");
-                    mounts.push(MountArgs {
-                        source: Some(root.join("root-python2")),
-                        target: rootfs.join("usr/lib/python2.7"),
-                        fstype: None,
-                        flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
-                        data: None,
-                    });
-                    execve_args.extend([
-                        String::from("/usr/bin/python2.7"),
-                        String::from(format!("{}.py", args.run_target)),
-                    ]);
-                }
-                args::Language::Python | args::Language::Python3 => {
-                    seccomp_profile_name = String::from("py");
-                    mounts.push(MountArgs {
-                        source: Some(root.join("root-python3")),
-                        target: rootfs.join("opt/python3"),
-                        fstype: None,
-                        flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
-                        data: None,
-                    });
-                    execve_args.extend([
-                        String::from("/usr/bin/python3.9"),
-                        format!("{}.py", args.run_target),
-                    ]);
-                }
-                args::Language::Ruby => {
-                    extra_memory_size_in_bytes = RUBY_EXTRA_MEMORY_SIZE_IN_BYTES;
-                    vm_memory_size_in_bytes = RUBY_VM_MEMORY_SIZE_IN_BYTES;
-                    seccomp_profile_name = String::from("ruby");
-                    mounts.push(MountArgs {
-                        source: Some(root.join("root-ruby")),
-                        target: rootfs.join("usr/lib/ruby"),
-                        fstype: None,
-                        flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
-                        data: None,
-                    });
-                    execve_args.extend([
-                        String::from("/usr/bin/ruby"),
-                        format!("{}.rb", args.run_target),
-                    ]);
-                }
-                args::Language::Rust => {
-                    seccomp_profile_name = String::from("rs");
-                    execve_args.extend([format!("./{}", args.run_target)]);
-                }
-                args::Language::Go => {
-                    extra_memory_size_in_bytes = GO_EXTRA_MEMORY_SIZE_IN_BYTES;
-                    seccomp_profile_name = String::from("go");
-                    execve_args.extend([format!("./{}", args.run_target)]);
-                }
-                args::Language::JavaScript => {
-                    seccomp_profile_name = String::from("js");
-                    extra_memory_size_in_bytes = NODE_EXTRA_MEMORY_SIZE_IN_BYTES;
-                    vm_memory_size_in_bytes = NODE_VM_MEMORY_SIZE_IN_BYTES;
-                    mounts.push(MountArgs {
-                        source: Some(root.join("root-js")),
-                        target: rootfs.join("opt/nodejs"),
-                        fstype: None,
-                        flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
-                        data: None,
-                    });
-                    execve_args.extend([
-                        String::from("/opt/nodejs/bin/node"),
-                        String::from("--jitless"),
-                        format!("{}.js", &args.run_target),
-                    ]);
-                }
-                args::Language::KarelPascal | args::Language::KarelJava => {
-                    seccomp_profile_name = String::from("karel");
-                    mounts.push(MountArgs {
-                        source: Some(root.join("root-js")),
-                        target: rootfs.join("opt/nodejs"),
-                        fstype: None,
-                        flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
-                        data: None,
-                    });
-                    execve_args.extend([
-                        String::from("/opt/nodejs/karel.wasm"),
-                        String::from(format!("{}.kx", &args.run_target)),
-                    ]);
-                }
-                args::Language::CSharp => {
-                    extra_memory_size_in_bytes = CLR_VM_MEMORY_SIZE_IN_BYTES;
-                    use_cgroups_for_memory_limit = true;
-                    vm_memory_size_in_bytes = CLR_VM_MEMORY_SIZE_IN_BYTES;
-                    seccomp_profile_name = String::from("cs");
-                    mounts.push(MountArgs {
-                        source: Some(root.join("root-dotnet")),
-                        target: rootfs.join("usr/share/dotnet"),
-                        fstype: None,
-                        flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
-                        data: None,
-                    });
-                    execve_args.extend([
-                        String::from("/usr/share/dotnet/dotnet"),
-                        format!("{}.dll", &args.run_target),
-                    ]);
-                    env.push("DOTNET_CLI_TELEMETRY_OPTOUT=1");
-                }
-            }
         }
 
-        for bind in args.bind {
-            let parts: Vec<&str> = bind.split(":").collect();
-
-            if parts.len() != 2 {
-                bail!("invalid bind description: {:?}", bind);
-            }
-
-            mounts.push(MountArgs {
-                source: Some(parts[0].into()),
-                target: rootfs.join(parts[1][1..].to_string()),
-                fstype: None,
-                flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
-                data: None,
-            });
+        for tmpfs in &args.tmpfs {
+            let (target, size, exec) = match tmpfs.split(':').collect::<Vec<&str>>()[..] {
+                [target, size] => (target, size, false),
+                [target, size, "exec"] => (target, size, true),
+                _ => bail!("invalid tmpfs description: {:?}", tmpfs),
+            };
+            let size = size
+                .parse()
+                .with_context(|| anyhow!("invalid tmpfs size: {:?}", tmpfs))?;
+            mounts.push(tmpfs_mount(&rootfs, target, size, None, exec)?);
         }
 
         execve_args.extend(args.extra_args);
 
-        let mut seccomp_bpf_filter_notify_contents = vec![];
-        let mut bpf_filter_path = root.join(format!("policies/{}.bpf", seccomp_profile_name));
-        File::open(&bpf_filter_path)
-            .with_context(|| format!("open {:?}", &bpf_filter_path))?
-            .read_to_end(&mut seccomp_bpf_filter_notify_contents)
-            .with_context(|| format!("read {:?}", &bpf_filter_path))?;
-
-        let mut seccomp_bpf_filter_sigsys_contents = vec![];
-        bpf_filter_path = root.join(format!("policies/sigsys/{}.bpf", seccomp_profile_name));
-        File::open(&bpf_filter_path)
-            .with_context(|| format!("open {:?}", &bpf_filter_path))?
-            .read_to_end(&mut seccomp_bpf_filter_sigsys_contents)
-            .with_context(|| format!("read {:?}", &bpf_filter_path))?;
+        let (seccomp_bpf_filter_notify_contents, seccomp_bpf_filter_sigsys_contents) = match &args
+            .seccomp_policy
+        {
+            Some(seccomp_policy) => {
+                let compiled =
+                    compile_file_cached(Path::new(seccomp_policy), &root.join("policies/cache"))?;
+                (compiled.notify, compiled.sigsys)
+            }
+            None => read_seccomp_bpf_filters(&root, &seccomp_profile_name)?,
+        };
 
         let (time_limit, wall_time_limit) = match args.time_limit {
             Some(time_limit) => (
@@ -787,6 +245,7 @@ impl JailOptions {
         Ok(JailOptions {
             disable_sandboxing: args.disable_sandboxing,
             homedir: PathBuf::from(args.homedir),
+            home_overlay,
             rootfs: rootfs,
             cgroup_path: Some(PathBuf::from(args.cgroup_path)),
             mounts: mounts,
@@ -794,11 +253,12 @@ impl JailOptions {
                 .iter()
                 .map(|s| CString::new(s.clone()))
                 .try_collect()?,
-            env: env.iter().map(|s| CString::new(*s)).try_collect()?,
+            env: env.iter().map(|s| CString::new(s.clone())).try_collect()?,
             seccomp_bpf_filter_notify_contents: seccomp_bpf_filter_notify_contents,
             seccomp_bpf_filter_sigsys_contents: seccomp_bpf_filter_sigsys_contents,
             seccomp_profile_name: seccomp_profile_name,
             meta: args.meta.map(|s| PathBuf::from(s)),
+            meta_format: args.meta_format,
 
             stdin: stdin,
             stdout: stdout,
@@ -807,36 +267,394 @@ impl JailOptions {
             time_limit: time_limit,
             wall_time_limit: wall_time_limit,
             output_limit: args.output_limit,
-            vm_memory_size_in_bytes: vm_memory_size_in_bytes,
-            use_cgroups_for_memory_limit: use_cgroups_for_memory_limit,
-            memory_limit: match args
-                .memory_limit
-                .map(|m| m.saturating_add(extra_memory_size_in_bytes))
-            {
-                Some(u64::MAX) => None,
-                Some(m) => Some(m),
-                None => None,
-            },
+            vm_memory_size_in_bytes: recipe.vm_memory_size_in_bytes,
+            use_cgroups_for_memory_limit: recipe.use_cgroups_for_memory_limit
+                || args.cgroup_memory_limit,
+            require_cgroups_for_memory_limit: recipe.use_cgroups_for_memory_limit,
+            memory_limit: recipe.memory_limit(args.memory_limit),
+            cgroup_memory_limit: recipe.cgroup_memory_limit(args.memory_limit),
+            pids_limit: args.pids_limit,
+            requested_memory_limit: args.memory_limit,
             allow_sigsys_fallback: args.allow_sigsys_fallback,
+            cpu: args.cpu,
+            extracts: args
+                .extract
+                .iter()
+                .map(|spec| Extract::parse(spec))
+                .try_collect()?,
+            extract_size_limit: args.extract_size_limit,
+            audit: args.audit_syscalls.map(PathBuf::from),
         })
     }
+
+    /// Redirects the sandboxed process' stdin to `fd` instead of a file mounted in the sandbox.
+    pub(crate) fn set_stdin_fd(&mut self, fd: RawFd) {
+        self.stdin = Stdio::FileDescriptor(fd);
+        self.remove_stdio_mount("stdin");
+    }
+
+    /// Redirects the sandboxed process' stdout to `fd` instead of a file mounted in the sandbox.
+    pub(crate) fn set_stdout_fd(&mut self, fd: RawFd) {
+        self.stdout = Stdio::FileDescriptor(fd);
+        self.remove_stdio_mount("stdout");
+    }
+
+    /// Redirects the sandboxed process' stderr to `fd` instead of a file mounted in the sandbox.
+    pub(crate) fn set_stderr_fd(&mut self, fd: RawFd) {
+        self.stderr = Stdio::FileDescriptor(fd);
+        self.remove_stdio_mount("stderr");
+    }
+
+    /// Redirects the sandboxed process' stdio to the provided paths and writes the .meta file to
+    /// `meta`, as if they had been passed in [`Args`](args::Args).
+    pub(crate) fn set_stdio(
+        &mut self,
+        stdin: Option<&str>,
+        stdout: Option<&str>,
+        stderr: Option<&str>,
+        meta: Option<&str>,
+    ) -> Result<()> {
+        for name in ["stdin", "stdout", "stderr"] {
+            self.remove_stdio_mount(name);
+        }
+        self.stdin = setup_stdin(&self.rootfs, stdin, &mut self.mounts)?;
+        self.stdout = setup_stdout(&self.rootfs, stdout, &mut self.mounts)?;
+        self.stderr = setup_stderr(&self.rootfs, stderr, &mut self.mounts)?;
+        self.meta = meta.map(PathBuf::from);
+        Ok(())
+    }
+
+    fn remove_stdio_mount(&mut self, name: &str) {
+        let target = self.rootfs.join("mnt/stdio").join(name);
+        self.mounts.retain(|mount_args| mount_args.target != target);
+    }
 }
 
-fn add_sources(execve_args: &mut Vec<String>, lang_flag: &str, compile_sources: &Vec<String>) {
-    let mut needs_flag = true;
-    for s in compile_sources.iter() {
-        if s.ends_with(".S") {
-            execve_args.extend([String::from("-xassembler-with-cpp"), s.clone()]);
-            needs_flag = true;
-        } else if s.ends_with(".s") {
-            execve_args.extend([String::from("-xassembler"), s.clone()]);
-            needs_flag = true;
-        } else {
-            if needs_flag {
-                execve_args.push(String::from(lang_flag));
-                needs_flag = false;
+/// Reads the prebuilt notify and sigsys variants of the seccomp filter for `seccomp_profile_name`.
+fn read_seccomp_bpf_filters(root: &Path, seccomp_profile_name: &str) -> Result<(Vec<u8>, Vec<u8>)> {
+    let mut seccomp_bpf_filter_notify_contents = vec![];
+    let mut bpf_filter_path = root.join(format!("policies/{}.bpf", seccomp_profile_name));
+    File::open(&bpf_filter_path)
+        .with_context(|| format!("open {:?}", &bpf_filter_path))?
+        .read_to_end(&mut seccomp_bpf_filter_notify_contents)
+        .with_context(|| format!("read {:?}", &bpf_filter_path))?;
+
+    let mut seccomp_bpf_filter_sigsys_contents = vec![];
+    bpf_filter_path = root.join(format!("policies/sigsys/{}.bpf", seccomp_profile_name));
+    File::open(&bpf_filter_path)
+        .with_context(|| format!("open {:?}", &bpf_filter_path))?
+        .read_to_end(&mut seccomp_bpf_filter_sigsys_contents)
+        .with_context(|| format!("read {:?}", &bpf_filter_path))?;
+
+    Ok((
+        seccomp_bpf_filter_notify_contents,
+        seccomp_bpf_filter_sigsys_contents,
+    ))
+}
+
+/// Returns the path outside of the sandbox of the absolute path `target` inside the sandbox.
+fn sandbox_path(rootfs: &Path, target: &str) -> Result<PathBuf> {
+    let target = Path::new(target);
+    if !target.is_absolute()
+        || target
+            .components()
+            .any(|component| component == Component::ParentDir)
+    {
+        bail!("mount target {:?} must be an absolute path", target);
+    }
+    Ok(rootfs.join(target.strip_prefix("/")?))
+}
+
+/// Returns the mount of a world-writable tmpfs at `target` in the sandbox.
+fn tmpfs_mount(
+    rootfs: &Path,
+    target: &str,
+    size: u64,
+    inodes: Option<u64>,
+    exec: bool,
+) -> Result<MountArgs> {
+    let target = sandbox_path(rootfs, target)?;
+    let mut flags = MsFlags::MS_NOSUID | MsFlags::MS_NODEV;
+    if !exec {
+        flags |= MsFlags::MS_NOEXEC;
+    }
+    let mut data = format!("size={},mode=1777", size);
+    if let Some(inodes) = inodes {
+        data.push_str(&format!(",nr_inodes={}", inodes));
+    }
+    Ok(MountArgs {
+        source: None,
+        target,
+        fstype: Some(String::from("tmpfs")),
+        flags,
+        data: Some(data),
+    })
+}
+
+/// Parses a `--bind` description. This is either `SOURCE:TARGET` for a read-only bind mount, or a
+/// comma-separated list of options. Descriptions that start with `type=` or contain a `,` are
+/// lists of options, so that paths with a `=` can still be bind-mounted the old way:
+///
+/// * `type=bind` (the default) or `type=tmpfs`.
+/// * `src=PATH`: the path outside the sandbox to bind-mount. Only for bind mounts.
+/// * `dst=PATH`: the absolute path in the sandbox where the mount is placed.
+/// * `ro` (the default for bind mounts) or `rw`: whether the bind mount is writable.
+/// * `nosuid`, `nodev`, `noexec`: restrict the bind mount further.
+/// * `size=BYTES` (required), `nr_inodes=COUNT`, and `exec`: the limits of the tmpfs, and whether
+///   files in it can be executed.
+fn parse_mount_spec(rootfs: &Path, spec: &str) -> Result<MountArgs> {
+    if !spec.starts_with("type=") && !spec.contains(',') {
+        let (source, target) = spec
+            .split_once(':')
+            .ok_or_else(|| anyhow!("expected SOURCE:TARGET or a list of options"))?;
+        return Ok(MountArgs {
+            source: Some(PathBuf::from(source)),
+            target: sandbox_path(rootfs, target)?,
+            fstype: None,
+            flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
+            data: None,
+        });
+    }
+
+    let mut fstype = "bind";
+    let mut source = None;
+    let mut target = None;
+    let mut flags = MsFlags::MS_RDONLY;
+    let mut size = None;
+    let mut inodes = None;
+    let mut exec = false;
+    for option in spec.split(',') {
+        match option.split_once('=') {
+            Some(("type", value)) => fstype = value,
+            Some(("src", value)) => source = Some(PathBuf::from(value)),
+            Some(("dst", value)) => target = Some(value),
+            Some(("size", value)) => {
+                size = Some(
+                    value
+                        .parse()
+                        .with_context(|| anyhow!("invalid size {:?}", value))?,
+                )
+            }
+            Some(("nr_inodes", value)) => {
+                inodes = Some(
+                    value
+                        .parse()
+                        .with_context(|| anyhow!("invalid nr_inodes {:?}", value))?,
+                )
+            }
+            Some(_) => bail!("unknown option {:?}", option),
+            None => match option {
+                "ro" => flags.insert(MsFlags::MS_RDONLY),
+                "rw" => flags.remove(MsFlags::MS_RDONLY),
+                "nosuid" => flags.insert(MsFlags::MS_NOSUID),
+                "nodev" => flags.insert(MsFlags::MS_NODEV),
+                "noexec" => flags.insert(MsFlags::MS_NOEXEC),
+                "exec" => exec = true,
+                _ => bail!("unknown option {:?}", option),
+            },
+        }
+    }
+    let target = target.ok_or_else(|| anyhow!("dst is missing"))?;
+
+    match fstype {
+        "bind" => {
+            if size.is_some() || inodes.is_some() || exec {
+                bail!("size, nr_inodes, and exec can only be used with type=tmpfs");
             }
-            execve_args.push(s.clone());
+            Ok(MountArgs {
+                source: Some(source.ok_or_else(|| anyhow!("src is missing"))?),
+                target: sandbox_path(rootfs, target)?,
+                fstype: None,
+                flags: MsFlags::MS_BIND | flags,
+                data: None,
+            })
+        }
+        "tmpfs" => {
+            if source.is_some() || flags != MsFlags::MS_RDONLY {
+                bail!("src, ro, rw, nosuid, nodev, and noexec can only be used with type=bind");
+            }
+            tmpfs_mount(
+                rootfs,
+                target,
+                size.ok_or_else(|| anyhow!("size is missing"))?,
+                inodes,
+                exec,
+            )
         }
+        _ => bail!("unknown type {:?}", fstype),
+    }
+}
+
+fn setup_stdin(rootfs: &Path, stdin: Option<&str>, mounts: &mut Vec<MountArgs>) -> Result<Stdio> {
+    Ok(if let Some(stdin) = stdin {
+        File::open(stdin).with_context(|| format!("open stdin {}", &stdin))?;
+        let source = PathBuf::from(
+            canonicalize(&stdin).with_context(|| format!("canonicalize({})", &stdin))?,
+        );
+        mounts.push(MountArgs {
+            source: Some(source.clone()),
+            target: rootfs.join("mnt/stdio/stdin"),
+            fstype: None,
+            flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
+            data: None,
+        });
+
+        Stdio::Mounted(source)
+    } else if unsafe { libc::isatty(libc::STDIN_FILENO) == 0 } {
+        let source = rootfs.join("dev/null");
+        mounts.push(MountArgs {
+            source: Some(source.clone()),
+            target: rootfs.join("mnt/stdio/stdin"),
+            fstype: None,
+            flags: MsFlags::MS_BIND | MsFlags::MS_RDONLY,
+            data: None,
+        });
+
+        Stdio::DevNull(source)
+    } else {
+        Stdio::FileDescriptor(libc::STDIN_FILENO)
+    })
+}
+
+fn setup_stdout(rootfs: &Path, stdout: Option<&str>, mounts: &mut Vec<MountArgs>) -> Result<Stdio> {
+    Ok(if let Some(stdout) = stdout {
+        File::create(stdout).with_context(|| format!("create stdout {}", &stdout))?;
+        let source = PathBuf::from(
+            canonicalize(&stdout).with_context(|| format!("canonicalize({})", &stdout))?,
+        );
+        mounts.push(MountArgs {
+            source: Some(source.clone()),
+            target: rootfs.join("mnt/stdio/stdout"),
+            fstype: None,
+            flags: MsFlags::MS_BIND,
+            data: None,
+        });
+
+        Stdio::Mounted(source)
+    } else if unsafe { libc::isatty(libc::STDOUT_FILENO) == 0 } {
+        let source = rootfs.join("dev/null");
+        mounts.push(MountArgs {
+            source: Some(source.clone()),
+            target: rootfs.join("mnt/stdio/stdout"),
+            fstype: None,
+            flags: MsFlags::MS_BIND,
+            data: None,
+        });
+
+        Stdio::DevNull(source)
+    } else {
+        Stdio::FileDescriptor(libc::STDOUT_FILENO)
+    })
+}
+
+fn setup_stderr(rootfs: &Path, stderr: Option<&str>, mounts: &mut Vec<MountArgs>) -> Result<Stdio> {
+    Ok(if let Some(stderr) = stderr {
+        File::options()
+            .append(true)
+            .create(true)
+            .open(stderr)
+            .with_context(|| format!("create stderr {}", &stderr))?;
+        let source = PathBuf::from(
+            canonicalize(&stderr).with_context(|| format!("canonicalize({})", &stderr))?,
+        );
+        mounts.push(MountArgs {
+            source: Some(source.clone()),
+            target: rootfs.join("mnt/stdio/stderr"),
+            fstype: None,
+            flags: MsFlags::MS_BIND,
+            data: None,
+        });
+
+        Stdio::Mounted(source)
+    } else if unsafe { libc::isatty(libc::STDERR_FILENO) == 0 } {
+        let source = rootfs.join("dev/null");
+        mounts.push(MountArgs {
+            source: Some(source.clone()),
+            target: rootfs.join("mnt/stdio/stderr"),
+            fstype: None,
+            flags: MsFlags::MS_BIND,
+            data: None,
+        });
+
+        Stdio::DevNull(source)
+    } else {
+        Stdio::FileDescriptor(libc::STDERR_FILENO)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use anyhow::Result;
+    use nix::mount::MsFlags;
+
+    use crate::jail::options::{parse_mount_spec, tmpfs_mount};
+
+    #[test]
+    fn test_tmpfs_mount() -> Result<()> {
+        let rootfs = Path::new("/var/lib/omegajail/root");
+        let mount = tmpfs_mount(rootfs, "/tmp", 1024, Some(16), false)?;
+        assert_eq!(mount.target, rootfs.join("tmp"));
+        assert_eq!(mount.fstype.as_deref(), Some("tmpfs"));
+        assert!(mount.flags.contains(MsFlags::MS_NOEXEC));
+        assert_eq!(
+            mount.data.as_deref(),
+            Some("size=1024,mode=1777,nr_inodes=16")
+        );
+
+        let mount = tmpfs_mount(rootfs, "/home/.cache", 1024, None, true)?;
+        assert_eq!(mount.target, rootfs.join("home/.cache"));
+        assert!(!mount.flags.contains(MsFlags::MS_NOEXEC));
+        assert_eq!(mount.data.as_deref(), Some("size=1024,mode=1777"));
+
+        assert!(tmpfs_mount(rootfs, "tmp", 1024, None, false).is_err());
+        assert!(tmpfs_mount(rootfs, "/tmp/../etc", 1024, None, false).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_mount_spec() -> Result<()> {
+        let rootfs = Path::new("/var/lib/omegajail/root");
+
+        let mount = parse_mount_spec(rootfs, "/data:/data")?;
+        assert_eq!(mount.source.as_deref(), Some(Path::new("/data")));
+        assert_eq!(mount.target, rootfs.join("data"));
+        assert_eq!(mount.flags, MsFlags::MS_BIND | MsFlags::MS_RDONLY);
+
+        let mount = parse_mount_spec(rootfs, "/srv/a=b:/mnt")?;
+        assert_eq!(mount.source.as_deref(), Some(Path::new("/srv/a=b")));
+        assert_eq!(mount.target, rootfs.join("mnt"));
+        assert_eq!(mount.flags, MsFlags::MS_BIND | MsFlags::MS_RDONLY);
+
+        let mount = parse_mount_spec(rootfs, "type=bind,src=/data,dst=/home/data,rw,noexec")?;
+        assert_eq!(mount.source.as_deref(), Some(Path::new("/data")));
+        assert_eq!(mount.target, rootfs.join("home/data"));
+        assert_eq!(mount.flags, MsFlags::MS_BIND | MsFlags::MS_NOEXEC);
+
+        let mount = parse_mount_spec(rootfs, "type=tmpfs,dst=/scratch,size=4096,exec")?;
+        assert_eq!(mount.source, None);
+        assert_eq!(mount.fstype.as_deref(), Some("tmpfs"));
+        assert_eq!(mount.data.as_deref(), Some("size=4096,mode=1777"));
+        assert!(!mount.flags.contains(MsFlags::MS_NOEXEC));
+
+        for spec in [
+            "/data",
+            "/data:data",
+            "src=/data,dst=data",
+            "src=/data",
+            "dst=/data",
+            "src=/data,dst=/data,size=4096",
+            "type=tmpfs,dst=/scratch",
+            "type=tmpfs,dst=/scratch,size=lots",
+            "type=tmpfs,src=/data,dst=/scratch,size=4096",
+            "type=overlay,dst=/data",
+            "src=/data,dst=/data,suid",
+        ] {
+            assert!(parse_mount_spec(rootfs, spec).is_err(), "{}", spec);
+        }
+
+        Ok(())
     }
 }
@@ -2,7 +2,7 @@ use std::fs::{read_to_string, File};
 use std::io::Write;
 use std::os::unix::io::AsRawFd;
 use std::os::unix::net::UnixStream;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::{anyhow, bail, Context, Result};
 use nix::unistd::{getgid, getuid, Pid};
@@ -12,22 +12,26 @@ use crate::jail::options::JailOptions;
 use crate::jail::{
     read_message, write_message, ParentSetupDoneEvent, SetupCgroupRequest, SetupCgroupResponse,
 };
-use crate::sys::RecvFile;
+use crate::sys::{RecvFile, SendFile};
 
 pub(crate) fn setup_child(
     parent_sock: &mut UnixStream,
     child: Pid,
     jail_options: &JailOptions,
-) -> Result<Vec<CGroup>> {
+) -> Result<(Vec<CGroup>, Option<File>)> {
     if !jail_options.disable_sandboxing {
         setup_ugid_mapping(child).context("setup child ugid mapping")?;
     }
     write_message(parent_sock, ParentSetupDoneEvent {}).context("write parent setup done event")?;
 
     read_message::<SetupCgroupRequest>(parent_sock).context("wait for setup cgroup request")?;
-    let cgroups = if !jail_options.disable_sandboxing {
+    let mut cpu_stat = None;
+    let mut killer = None;
+    let mut pids_limit_enforced = false;
+    let mut memory_limit_enforced = false;
+    let (cgroups, jailed_pidfd) = if !jail_options.disable_sandboxing {
         let pidfd = parent_sock.recv_file().context("receive seccomp pidfd")?;
-        match &jail_options.cgroup_path {
+        let cgroups = match &jail_options.cgroup_path {
             Some(cgroup_path_root) => {
                 let pid = get_pid_from_pidfd(&pidfd).context("get jailed pid")?;
                 let cgroup_path = cgroup_path_root.join(&jail_options.seccomp_profile_name);
@@ -40,25 +44,100 @@ pub(crate) fn setup_child(
                     .add_pid(pid)
                     .with_context(|| anyhow!("add {} to cgroup", pid))?;
                 if jail_options.use_cgroups_for_memory_limit {
-                    if let Some(memory_limit) = jail_options.memory_limit {
-                        cgroup.set_memory_limit(memory_limit).with_context(|| {
-                            anyhow!("set pid {}'s memory limit to {}", pid, memory_limit)
-                        })?;
+                    if let Some(memory_limit) = jail_options.cgroup_memory_limit {
+                        match cgroup.set_memory_limit(memory_limit) {
+                            Ok(()) => memory_limit_enforced = true,
+                            Err(err) if jail_options.require_cgroups_for_memory_limit => {
+                                return Err(err).with_context(|| {
+                                    anyhow!("set pid {}'s memory limit to {}", pid, memory_limit)
+                                });
+                            }
+                            Err(err) => {
+                                log::warn!(
+                                    "set pid {}'s memory limit to {}: {:#}",
+                                    pid,
+                                    memory_limit,
+                                    err
+                                );
+                            }
+                        }
                     }
                 }
-                vec![cgroup]
+                cpu_stat = cgroup.open_cpu_stat().context("open cgroup cpu.stat")?;
+                killer = cgroup.open_killer().context("open cgroup kill files")?;
+                let mut cgroups = vec![cgroup];
+                if let Some(pids_limit) = jail_options.pids_limit {
+                    match setup_pids_cgroup(&mut cgroups, &cgroup_path, pid, pids_limit) {
+                        Ok(()) => pids_limit_enforced = true,
+                        Err(err) => {
+                            log::warn!(
+                                "could not enforce the pids limit through a cgroup, falling back \
+                                 to RLIMIT_NPROC: {:#}",
+                                err
+                            );
+                        }
+                    }
+                }
+                cgroups
             }
             None => {
                 vec![]
             }
-        }
+        };
+        (cgroups, Some(pidfd))
     } else {
-        vec![]
+        (vec![], None)
     };
 
-    write_message(parent_sock, SetupCgroupResponse {}).context("write setup cgroup response")?;
+    write_message(
+        parent_sock,
+        SetupCgroupResponse {
+            cpu_stat_available: cpu_stat.is_some(),
+            pids_limit_enforced,
+            memory_limit_enforced,
+            killer_available: killer.is_some(),
+        },
+    )
+    .context("write setup cgroup response")?;
+    if let Some(cpu_stat) = cpu_stat {
+        parent_sock
+            .send_file(cpu_stat)
+            .context("send cgroup cpu.stat")?;
+    }
+    if let Some(killer) = killer {
+        parent_sock
+            .send_file(killer.freeze)
+            .context("send cgroup.freeze")?;
+        parent_sock
+            .send_file(killer.kill)
+            .context("send cgroup.kill")?;
+    }
 
-    Ok(cgroups)
+    Ok((cgroups, jailed_pidfd))
+}
+
+fn setup_pids_cgroup(
+    cgroups: &mut Vec<CGroup>,
+    cgroup_path: &Path,
+    pid: Pid,
+    pids_limit: u64,
+) -> Result<()> {
+    if CGroup::is_cgroup_v2() {
+        // The pids controller lives in the same hierarchy as the memory one.
+        return cgroups[0]
+            .set_pids_limit(pids_limit)
+            .with_context(|| anyhow!("set pid {}'s pids limit to {}", pid, pids_limit));
+    }
+    let cgroup = CGroup::new("pids", cgroup_path)
+        .with_context(|| anyhow!("create pids cgroup {:?}", cgroup_path))?;
+    cgroup
+        .add_pid(pid)
+        .with_context(|| anyhow!("add {} to pids cgroup", pid))?;
+    cgroup
+        .set_pids_limit(pids_limit)
+        .with_context(|| anyhow!("set pid {}'s pids limit to {}", pid, pids_limit))?;
+    cgroups.push(cgroup);
+    Ok(())
 }
 
 fn get_pid_from_pidfd(pidfd: &File) -> Result<Pid> {
@@ -0,0 +1,529 @@
+//! A long-running daemon that spawns [`Jail`](crate::jail::Jail)s on behalf of clients connected
+//! through a Unix socket.
+//!
+//! Every message is a flexbuffers-serialized value preceded by its size as a big-endian 64-bit
+//! integer. Clients send a [`ServeRequest`], followed by the file descriptors that were announced
+//! in it (in stdin, stdout, stderr order), and receive a [`ServeResponse`] once the sandboxed
+//! process has exited. Several requests can be sent through the same connection one after the
+//! other, and requests sent through different connections are run concurrently.
+//!
+//! Clients can only choose what to run and its limits. Everything that affects the sandbox itself
+//! (the runtime root, the cgroups, the mounts, and the seccomp policy) is set by the daemon in a
+//! [`ServeConfig`], and the daemon never opens any paths on behalf of the clients other than the
+//! home directory, which must be within [`ServeConfig::homedir_root`].
+
+use std::convert::TryFrom;
+use std::fs::{remove_file, set_permissions, File, Permissions};
+use std::io::ErrorKind;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Component, Path};
+
+use anyhow::{anyhow, bail, Context, Result};
+use nix::errno::Errno;
+use nix::sys::stat::{umask, Mode};
+use nix::sys::wait::{self, waitpid, WaitPidFlag};
+use nix::unistd::{fork, ForkResult};
+use serde::{Deserialize, Serialize};
+
+use crate::args;
+use crate::jail::{read_message, write_message, Command, JailResult};
+use crate::sys::{RecvFile, SendFile};
+
+/// The settings of the daemon that apply to every sandboxed process, regardless of the request.
+#[derive(Debug, Clone)]
+pub struct ServeConfig {
+    /// Root of the omegajail runtime.
+    pub root: String,
+    /// The directory that contains the home directories that clients can choose.
+    pub homedir_root: String,
+    /// The cgroup hierarchy in which processes will be placed.
+    pub cgroup_path: String,
+    /// Additional mounts, in the same format as [`Args::bind`](args::Args::bind).
+    pub bind: Vec<String>,
+    /// Whether downgrading to the SIGSYS-based seccomp filter is allowed.
+    pub allow_sigsys_fallback: bool,
+}
+
+/// The arguments of a sandboxed process that clients of the daemon can choose. These have the
+/// same meaning as the fields of [`Args`](args::Args) with the same name, except for `homedir`,
+/// which is relative to [`ServeConfig::homedir_root`].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ServeArgs {
+    pub compile: Option<String>,
+    pub compile_source: Option<Vec<String>>,
+    pub compile_target: String,
+    pub run: Option<String>,
+    pub run_target: String,
+    pub homedir: String,
+    pub homedir_writable: bool,
+    pub homedir_overlay: bool,
+    pub homedir_overlay_size: u64,
+    pub homedir_overlay_inodes: u64,
+    pub tmp_size: u64,
+    pub tmp_inodes: Option<u64>,
+    pub tmp_exec: bool,
+    pub time_limit: Option<u64>,
+    pub extra_wall_time_limit: u64,
+    pub output_limit: Option<u64>,
+    pub memory_limit: Option<u64>,
+    pub cgroup_memory_limit: bool,
+    pub pids_limit: Option<u64>,
+    pub cpu: Option<usize>,
+    pub extra_args: Vec<String>,
+}
+
+impl TryFrom<&args::Args> for ServeArgs {
+    type Error = anyhow::Error;
+
+    /// Takes the arguments that clients can choose from `args`, failing if any of the ones that
+    /// the daemon does not support are set. `root` and `cgroup_path` are ignored, since the ones
+    /// in the [`ServeConfig`] are always used.
+    fn try_from(args: &args::Args) -> Result<ServeArgs> {
+        for (flag, set) in [
+            ("--disable-sandboxing", args.disable_sandboxing),
+            ("--batch", args.batch.is_some()),
+            ("--interactor", args.interactor.is_some()),
+            ("--audit-syscalls", args.audit_syscalls.is_some()),
+            ("--extract", !args.extract.is_empty()),
+            ("--bind", !args.bind.is_empty()),
+            ("--tmpfs", !args.tmpfs.is_empty()),
+            ("--seccomp-policy", args.seccomp_policy.is_some()),
+            ("--allow-sigsys-fallback", args.allow_sigsys_fallback),
+            ("--stdin", args.stdin.is_some()),
+            ("--stdout", args.stdout.is_some()),
+            ("--stderr", args.stderr.is_some()),
+            ("--meta", args.meta.is_some()),
+        ] {
+            if set {
+                bail!("{} is not supported by the daemon", flag);
+            }
+        }
+        Ok(ServeArgs {
+            compile: args.compile.clone(),
+            compile_source: args.compile_source.clone(),
+            compile_target: args.compile_target.clone(),
+            run: args.run.clone(),
+            run_target: args.run_target.clone(),
+            homedir: args.homedir.clone(),
+            homedir_writable: args.homedir_writable,
+            homedir_overlay: args.homedir_overlay,
+            homedir_overlay_size: args.homedir_overlay_size,
+            homedir_overlay_inodes: args.homedir_overlay_inodes,
+            tmp_size: args.tmp_size,
+            tmp_inodes: args.tmp_inodes,
+            tmp_exec: args.tmp_exec,
+            time_limit: args.time_limit,
+            extra_wall_time_limit: args.extra_wall_time_limit,
+            output_limit: args.output_limit,
+            memory_limit: args.memory_limit,
+            cgroup_memory_limit: args.cgroup_memory_limit,
+            pids_limit: args.pids_limit,
+            cpu: args.cpu,
+            extra_args: args.extra_args.clone(),
+        })
+    }
+}
+
+impl ServeConfig {
+    /// Returns the full arguments of the sandboxed process for a request. Fails if the requested
+    /// home directory is not within [`ServeConfig::homedir_root`].
+    fn args(&self, request: ServeArgs) -> Result<args::Args> {
+        let homedir = Path::new(&request.homedir);
+        if homedir.as_os_str().is_empty()
+            || !homedir
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        {
+            bail!(
+                "{:?} is not a relative path within the home directory root",
+                homedir
+            );
+        }
+        let homedir_root = Path::new(&self.homedir_root)
+            .canonicalize()
+            .with_context(|| anyhow!("canonicalize({})", &self.homedir_root))?;
+        // The home directory could still be a symlink to somewhere else.
+        let homedir = homedir_root
+            .join(homedir)
+            .canonicalize()
+            .with_context(|| anyhow!("canonicalize({})", &request.homedir))?;
+        if !homedir.starts_with(&homedir_root) {
+            bail!(
+                "{:?} is not a relative path within the home directory root",
+                &request.homedir
+            );
+        }
+        Ok(args::Args {
+            root: self.root.clone(),
+            compile: request.compile,
+            compile_source: request.compile_source,
+            compile_target: request.compile_target,
+            run: request.run,
+            run_target: request.run_target,
+            homedir: homedir.to_string_lossy().into_owned(),
+            homedir_writable: request.homedir_writable,
+            homedir_overlay: request.homedir_overlay,
+            homedir_overlay_size: request.homedir_overlay_size,
+            homedir_overlay_inodes: request.homedir_overlay_inodes,
+            tmp_size: request.tmp_size,
+            tmp_inodes: request.tmp_inodes,
+            tmp_exec: request.tmp_exec,
+            tmpfs: vec![],
+            extract: vec![],
+            extract_size_limit: 0,
+            stdin: None,
+            stdout: None,
+            stderr: None,
+            meta: None,
+            meta_format: args::MetaFormat::Text,
+            time_limit: request.time_limit,
+            extra_wall_time_limit: request.extra_wall_time_limit,
+            output_limit: request.output_limit,
+            memory_limit: request.memory_limit,
+            cgroup_memory_limit: request.cgroup_memory_limit,
+            pids_limit: request.pids_limit,
+            cpu: request.cpu,
+            cgroup_path: self.cgroup_path.clone(),
+            disable_sandboxing: false,
+            bind: self.bind.clone(),
+            seccomp_policy: None,
+            audit_syscalls: None,
+            allow_sigsys_fallback: self.allow_sigsys_fallback,
+            batch: None,
+            interactor: None,
+            interactor_target: String::new(),
+            interactor_homedir: None,
+            interactor_meta: None,
+            interactor_stderr: None,
+            interactor_time_limit: None,
+            interactor_memory_limit: None,
+            interactor_arg: vec![],
+            extra_args: request.extra_args,
+        })
+    }
+}
+
+/// A request to run a single sandboxed process.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ServeRequest {
+    /// The arguments of the sandboxed process.
+    pub args: ServeArgs,
+    /// Whether the file descriptor for stdin follows the request.
+    pub stdin_fd: bool,
+    /// Whether the file descriptor for stdout follows the request.
+    pub stdout_fd: bool,
+    /// Whether the file descriptor for stderr follows the request.
+    pub stderr_fd: bool,
+}
+
+/// The response to a [`ServeRequest`].
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ServeResponse {
+    /// The sandboxed process was run, and this is its result.
+    Result(JailResult),
+    /// The sandboxed process could not be run.
+    Error(String),
+}
+
+/// Sends a request to run a sandboxed process with `args` to an omegajail daemon through
+/// `stream`, and waits for its result. The sandboxed process' stdio is redirected to the files, or
+/// to `/dev/null` if they are not provided. Fails without sending anything if `args` sets any
+/// option that the daemon does not support (see [`ServeArgs`]).
+pub fn send_request(
+    stream: &mut UnixStream,
+    args: &args::Args,
+    stdin: Option<File>,
+    stdout: Option<File>,
+    stderr: Option<File>,
+) -> Result<JailResult> {
+    write_message(
+        stream,
+        ServeRequest {
+            args: ServeArgs::try_from(args)?,
+            stdin_fd: stdin.is_some(),
+            stdout_fd: stdout.is_some(),
+            stderr_fd: stderr.is_some(),
+        },
+    )
+    .context("write request")?;
+    for file in [stdin, stdout, stderr].into_iter().flatten() {
+        stream.send_file(file).context("send file")?;
+    }
+    match read_message::<ServeResponse>(stream).context("read response")? {
+        ServeResponse::Result(result) => Ok(result),
+        ServeResponse::Error(err) => bail!("jail failed: {}", err),
+    }
+}
+
+/// Listens for connections on the Unix socket at `socket`, with permissions `socket_mode`, and
+/// serves their requests forever with `config`.
+///
+/// Each connection is handled by a separate worker process, so that the spawning of the sandboxed
+/// processes always happens from a single-threaded process.
+pub fn serve<P: AsRef<Path>>(socket: P, socket_mode: u32, config: ServeConfig) -> Result<()> {
+    let socket = socket.as_ref();
+    // Remove the socket left behind by a previous instance.
+    match remove_file(socket) {
+        Err(err) if err.kind() != ErrorKind::NotFound => {
+            return Err(err).with_context(|| anyhow!("remove {:?}", socket));
+        }
+        _ => {}
+    }
+    // Create the socket without any permissions, so that nobody can connect to it before it has
+    // the ones it should have.
+    let old_umask = umask(Mode::all());
+    let listener = UnixListener::bind(socket);
+    umask(old_umask);
+    let listener = listener.with_context(|| anyhow!("bind {:?}", socket))?;
+    set_permissions(socket, Permissions::from_mode(socket_mode))
+        .with_context(|| anyhow!("chmod {:?} to {:o}", socket, socket_mode))?;
+    log::info!("listening on {:?}", socket);
+
+    loop {
+        let conn = match listener.accept() {
+            Ok((conn, _)) => conn,
+            Err(err) => {
+                log::error!("accept: {:#}", err);
+                continue;
+            }
+        };
+        reap_workers();
+
+        match unsafe { fork() }.context("fork")? {
+            ForkResult::Parent { child, .. } => {
+                log::debug!("spawned worker {}", child);
+            }
+            ForkResult::Child => {
+                std::mem::drop(listener);
+                match handle_connection(conn, &config) {
+                    Ok(()) => unsafe { libc::exit(0) },
+                    Err(err) => {
+                        log::error!("connection failed: {:#}", err);
+                        unsafe { libc::exit(1) }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Waits for any worker processes that have exited so that they don't linger as zombies.
+fn reap_workers() {
+    loop {
+        match waitpid(None, Some(WaitPidFlag::WNOHANG)) {
+            Err(Errno::EINTR) => {
+                continue;
+            }
+            Err(Errno::ECHILD) | Ok(wait::WaitStatus::StillAlive) => {
+                break;
+            }
+            Err(err) => {
+                log::error!("waitpid(-1, WNOHANG): {:#}", err);
+                break;
+            }
+            Ok(_) => {}
+        }
+    }
+}
+
+fn handle_connection(mut conn: UnixStream, config: &ServeConfig) -> Result<()> {
+    loop {
+        let request = match read_message::<ServeRequest>(&mut conn) {
+            Ok(request) => request,
+            Err(err) => {
+                // The client closed the connection.
+                if let Some(io_err) = err.downcast_ref::<std::io::Error>() {
+                    if io_err.kind() == ErrorKind::UnexpectedEof {
+                        return Ok(());
+                    }
+                }
+                // Let the client know why its request was rejected (e.g. it sets options that the
+                // daemon does not support). The file descriptors that may follow cannot be told
+                // apart from the next request, so the connection is closed afterwards.
+                let err = err.context("read request");
+                if let Err(write_err) =
+                    write_message(&mut conn, ServeResponse::Error(format!("{:#}", err)))
+                {
+                    log::error!("write response: {:#}", write_err);
+                }
+                return Err(err);
+            }
+        };
+        // The file descriptors are received even if the request is rejected, so that they are not
+        // mistaken for the next request.
+        let stdin = match request.stdin_fd {
+            true => Some(conn.recv_file().context("receive stdin")?),
+            false => None,
+        };
+        let stdout = match request.stdout_fd {
+            true => Some(conn.recv_file().context("receive stdout")?),
+            false => None,
+        };
+        let stderr = match request.stderr_fd {
+            true => Some(conn.recv_file().context("receive stderr")?),
+            false => None,
+        };
+        let response = match config.args(request.args).and_then(|args| {
+            let mut command = Command::new(args);
+            if let Some(stdin) = stdin {
+                command = command.stdin(stdin);
+            }
+            if let Some(stdout) = stdout {
+                command = command.stdout(stdout);
+            }
+            if let Some(stderr) = stderr {
+                command = command.stderr(stderr);
+            }
+            command.spawn().and_then(|jail| jail.wait())
+        }) {
+            Ok(result) => ServeResponse::Result(result),
+            Err(err) => ServeResponse::Error(format!("{:#}", err)),
+        };
+        write_message(&mut conn, response).context("write response")?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::create_dir;
+    use std::os::unix::fs::symlink;
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+
+    use anyhow::Result;
+    use clap::Parser;
+    use serde::Serialize;
+    use tempdir::TempDir;
+
+    use crate::args::Args;
+    use crate::jail::serve::{handle_connection, send_request, ServeConfig, ServeResponse};
+    use crate::jail::{read_message, write_message};
+
+    fn parse_args(extra: &[&str]) -> Result<Args> {
+        Ok(Args::try_parse_from(
+            ["omegajail", "--homedir", "home", "--run", "c"]
+                .iter()
+                .chain(extra),
+        )?)
+    }
+
+    #[test]
+    fn test_handle_connection() -> Result<()> {
+        let homedir_root = TempDir::new("homedir_root")?;
+        create_dir(homedir_root.path().join("home"))?;
+        let (mut client, server) = UnixStream::pair()?;
+        let config = ServeConfig {
+            root: "/nonexistent".to_string(),
+            homedir_root: homedir_root.path().to_string_lossy().into_owned(),
+            cgroup_path: "/omegajail".to_string(),
+            bind: vec![],
+            allow_sigsys_fallback: false,
+        };
+        let handle = thread::spawn(move || handle_connection(server, &config));
+
+        // The root of the daemon is always used, and an invalid one should be reported back to
+        // the client without dropping the connection.
+        for _ in 0..2 {
+            let args = parse_args(&["--root", "/"])?;
+            let err = send_request(&mut client, &args, None, None, None).unwrap_err();
+            assert!(
+                format!("{:#}", err).contains("canonicalize(/nonexistent)"),
+                "{:#}",
+                err
+            );
+        }
+
+        std::mem::drop(client);
+        handle.join().unwrap()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_unsupported_options() -> Result<()> {
+        for extra in [
+            &["--disable-sandboxing"][..],
+            &["--interactor", "c"],
+            &["--batch", "cases.toml"],
+            &["--audit-syscalls", "/tmp/audit"],
+            &["--extract", "output.txt:/etc/passwd"],
+            &["--meta", "/etc/passwd"],
+            &["--bind", "/:/mnt"],
+        ] {
+            let (mut client, _server) = UnixStream::pair()?;
+            let err = send_request(&mut client, &parse_args(extra)?, None, None, None).unwrap_err();
+            assert!(
+                format!("{:#}", err).contains(&format!("{} is not supported", extra[0])),
+                "{:#}",
+                err
+            );
+        }
+
+        // The daemon rejects the request too, in case the client does not check it.
+        #[derive(Serialize)]
+        struct UncheckedRequest {
+            args: Args,
+            stdin_fd: bool,
+            stdout_fd: bool,
+            stderr_fd: bool,
+        }
+        let (mut client, server) = UnixStream::pair()?;
+        let config = ServeConfig {
+            root: ".".to_string(),
+            homedir_root: ".".to_string(),
+            cgroup_path: "/omegajail".to_string(),
+            bind: vec![],
+            allow_sigsys_fallback: false,
+        };
+        let handle = thread::spawn(move || handle_connection(server, &config));
+        write_message(
+            &mut client,
+            UncheckedRequest {
+                args: parse_args(&["--disable-sandboxing"])?,
+                stdin_fd: false,
+                stdout_fd: false,
+                stderr_fd: false,
+            },
+        )?;
+        match read_message::<ServeResponse>(&mut client)? {
+            ServeResponse::Error(err) => assert!(err.contains("unknown field"), "{}", err),
+            ServeResponse::Result(result) => panic!("unexpected result {:?}", result),
+        }
+        assert!(handle.join().unwrap().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_homedir_outside_root() -> Result<()> {
+        let homedir_root = TempDir::new("homedir_root")?;
+        symlink("/", homedir_root.path().join("escape"))?;
+        let (mut client, server) = UnixStream::pair()?;
+        let config = ServeConfig {
+            root: "/nonexistent".to_string(),
+            homedir_root: homedir_root.path().to_string_lossy().into_owned(),
+            cgroup_path: "/omegajail".to_string(),
+            bind: vec![],
+            allow_sigsys_fallback: false,
+        };
+        let handle = thread::spawn(move || handle_connection(server, &config));
+
+        for homedir in ["/", "..", "home/../..", "escape"] {
+            let mut args = parse_args(&["--homedir-writable"])?;
+            args.homedir = homedir.to_string();
+            let err = send_request(&mut client, &args, None, None, None).unwrap_err();
+            assert!(
+                format!("{:#}", err).contains("is not a relative path within the home directory"),
+                "{}: {:#}",
+                homedir,
+                err
+            );
+        }
+
+        std::mem::drop(client);
+        handle.join().unwrap()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+//! Classification of the result of a sandboxed process into a [`Verdict`].
+
+use std::time::Duration;
+
+use nix::sys::signal::Signal;
+
+use crate::sys::{KilledBy, Verdict, WaitStatus, WaitidStatus};
+
+/// Classifies `status` given the limits the process was run with. `memory_limit` is the limit
+/// requested by the caller, without any of the language's overhead.
+///
+/// When several limits were exceeded, the one that most likely caused the process to terminate
+/// is reported. A contestant's program that was terminated because the interactor exited first is
+/// not considered to have failed, since its answer is judged by the interactor.
+pub(crate) fn classify(
+    status: &WaitidStatus,
+    time_limit: Option<Duration>,
+    memory_limit: Option<u64>,
+    output_limit: Option<u64>,
+) -> Verdict {
+    match status.status {
+        WaitStatus::Syscalled(_, _) => {
+            return Verdict::RestrictedFunction;
+        }
+        WaitStatus::Signaled(_, Signal::SIGXCPU) => {
+            return Verdict::TimeLimitExceeded;
+        }
+        WaitStatus::Signaled(_, Signal::SIGXFSZ) if output_limit.is_some() => {
+            return Verdict::OutputLimitExceeded;
+        }
+        _ => {}
+    }
+    if let Some(time_limit) = time_limit {
+        if status.user_time + status.system_time > time_limit {
+            return Verdict::TimeLimitExceeded;
+        }
+    }
+    // The OOM killer sends a plain SIGKILL, which would otherwise be classified as a runtime error.
+    if status.oom_killed {
+        return Verdict::MemoryLimitExceeded;
+    }
+    if let Some(memory_limit) = memory_limit {
+        if status.max_rss > memory_limit {
+            return Verdict::MemoryLimitExceeded;
+        }
+        // When the memory cgroup enforces the limit, allocations fail once the peak reaches it,
+        // and the process usually exits with an error instead of being killed.
+        if !matches!(status.status, WaitStatus::Exited(_, 0))
+            && status
+                .cgroup_max_memory
+                .is_some_and(|peak| peak >= memory_limit)
+        {
+            return Verdict::MemoryLimitExceeded;
+        }
+    }
+    match (&status.status, status.killed_by) {
+        (WaitStatus::Exited(_, 0), _) => Verdict::Ok,
+        (_, Some(KilledBy::Interactor)) => Verdict::Ok,
+        _ => Verdict::RuntimeError,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use nix::sys::signal::Signal;
+    use nix::unistd::Pid;
+
+    use crate::jail::verdict::classify;
+    use crate::sys::{KilledBy, ResourceUsage, Verdict, WaitStatus, WaitidStatus};
+
+    fn result(status: WaitStatus, time_ms: u64, max_rss: u64) -> WaitidStatus {
+        WaitidStatus {
+            status,
+            verdict: Verdict::JudgeError,
+            user_time: Duration::from_millis(time_ms),
+            system_time: Duration::ZERO,
+            wall_time: Duration::from_millis(time_ms),
+            max_rss,
+            cgroup_max_memory: None,
+            rusage: ResourceUsage::default(),
+            killed_by: None,
+            pids_limit_exceeded: false,
+            oom_killed: false,
+            extracted: vec![],
+        }
+    }
+
+    #[test]
+    fn test_classify() {
+        let pid = Pid::from_raw(2);
+        let time_limit = Some(Duration::from_millis(1000));
+        let memory_limit = Some(64 * 1024 * 1024);
+        let output_limit = Some(1024);
+        for (status, time_ms, max_rss, expected) in [
+            (WaitStatus::Exited(pid, 0), 10, 1024, Verdict::Ok),
+            (WaitStatus::Exited(pid, 1), 10, 1024, Verdict::RuntimeError),
+            (
+                WaitStatus::Signaled(pid, Signal::SIGSEGV),
+                10,
+                1024,
+                Verdict::RuntimeError,
+            ),
+            (
+                WaitStatus::Syscalled(pid, 57),
+                2000,
+                128 * 1024 * 1024,
+                Verdict::RestrictedFunction,
+            ),
+            (
+                WaitStatus::Signaled(pid, Signal::SIGXCPU),
+                900,
+                1024,
+                Verdict::TimeLimitExceeded,
+            ),
+            (
+                WaitStatus::Exited(pid, 0),
+                1001,
+                1024,
+                Verdict::TimeLimitExceeded,
+            ),
+            (
+                WaitStatus::Signaled(pid, Signal::SIGXFSZ),
+                10,
+                1024,
+                Verdict::OutputLimitExceeded,
+            ),
+            (
+                WaitStatus::Signaled(pid, Signal::SIGKILL),
+                10,
+                65 * 1024 * 1024,
+                Verdict::MemoryLimitExceeded,
+            ),
+        ] {
+            assert_eq!(
+                classify(
+                    &result(status, time_ms, max_rss),
+                    time_limit,
+                    memory_limit,
+                    output_limit
+                ),
+                expected
+            );
+        }
+
+        // A process that fails to allocate more memory once it reaches the limit of the memory
+        // cgroup usually exits with an error, and one that is OOM-killed is sent a SIGKILL.
+        let mut allocation_failed = result(WaitStatus::Exited(pid, 1), 10, 1024);
+        allocation_failed.cgroup_max_memory = memory_limit;
+        assert_eq!(
+            classify(&allocation_failed, time_limit, memory_limit, output_limit),
+            Verdict::MemoryLimitExceeded
+        );
+        allocation_failed.cgroup_max_memory = Some(64 * 1024 * 1024 - 4096);
+        assert_eq!(
+            classify(&allocation_failed, time_limit, memory_limit, output_limit),
+            Verdict::RuntimeError
+        );
+        let mut reached_limit = result(WaitStatus::Exited(pid, 0), 10, 1024);
+        reached_limit.cgroup_max_memory = memory_limit;
+        assert_eq!(
+            classify(&reached_limit, time_limit, memory_limit, output_limit),
+            Verdict::Ok
+        );
+        let mut oom_killed = result(WaitStatus::Signaled(pid, Signal::SIGKILL), 10, 1024);
+        oom_killed.oom_killed = true;
+        assert_eq!(
+            classify(&oom_killed, time_limit, memory_limit, output_limit),
+            Verdict::MemoryLimitExceeded
+        );
+
+        // A contestant's program that is terminated because the interactor exited first did not
+        // fail, unless it had already exceeded a limit.
+        let mut killed = result(WaitStatus::Signaled(pid, Signal::SIGKILL), 10, 1024);
+        killed.killed_by = Some(KilledBy::Interactor);
+        assert_eq!(
+            classify(&killed, time_limit, memory_limit, output_limit),
+            Verdict::Ok
+        );
+        killed.user_time = Duration::from_millis(1001);
+        assert_eq!(
+            classify(&killed, time_limit, memory_limit, output_limit),
+            Verdict::TimeLimitExceeded
+        );
+
+        // Without limits, only the status matters.
+        assert_eq!(
+            classify(
+                &result(WaitStatus::Signaled(pid, Signal::SIGXFSZ), 10, 1024),
+                None,
+                None,
+                None
+            ),
+            Verdict::RuntimeError
+        );
+        assert_eq!(
+            classify(
+                &result(WaitStatus::Exited(pid, 0), 5000, 1 << 40),
+                None,
+                None,
+                None
+            ),
+            Verdict::Ok
+        );
+    }
+}
@@ -0,0 +1,444 @@
+//! The registry of languages supported by the jail.
+//!
+//! The default registry is the `languages.toml` file at the root of the crate, which is embedded
+//! in the binary. A `languages.toml` file in the omegajail runtime root can add new languages or
+//! replace the default definition of existing ones, so that adding a compiler version does not
+//! require a new release.
+
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+
+const DEFAULT_REGISTRY_CONTENTS: &str = include_str!("../languages.toml");
+const DEFAULT_EXTRA_MEMORY_SIZE_IN_BYTES: u64 = 16 * 1024 * 1024;
+
+/// The name of the file in the omegajail runtime root that extends the default registry.
+pub(crate) const REGISTRY_FILENAME: &str = "languages.toml";
+
+/// An additional read-only bind-mount needed by a language.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct LanguageMount {
+    /// The path of the directory to mount, relative to the omegajail runtime root.
+    pub source: PathBuf,
+    /// The absolute path in the sandbox where the directory will be mounted.
+    pub target: PathBuf,
+}
+
+/// How to either compile or run a program.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct Recipe {
+    pub seccomp_profile: String,
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: Vec<String>,
+    #[serde(default)]
+    pub mounts: Vec<LanguageMount>,
+    pub source_language_flag: Option<String>,
+    #[serde(default)]
+    pub memory_limit_args: Vec<String>,
+    #[serde(default)]
+    pub min_heap_size_in_bytes: u64,
+    #[serde(default = "default_extra_memory_size_in_bytes")]
+    pub extra_memory_size_in_bytes: u64,
+    #[serde(default)]
+    pub vm_memory_size_in_bytes: u64,
+    #[serde(default = "default_true")]
+    pub enforce_memory_limit: bool,
+    #[serde(default)]
+    pub use_cgroups_for_memory_limit: bool,
+    #[serde(default)]
+    pub cgroup_extra_memory_size_in_bytes: u64,
+}
+
+fn default_extra_memory_size_in_bytes() -> u64 {
+    DEFAULT_EXTRA_MEMORY_SIZE_IN_BYTES
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// The definition of a single language.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Language {
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    pub compile: Option<Recipe>,
+    pub run: Option<Recipe>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct RegistryFile {
+    #[serde(default)]
+    languages: BTreeMap<String, Language>,
+}
+
+/// The values that are substituted in the placeholders of a [`Recipe`]'s arguments.
+pub(crate) struct RecipeContext<'a> {
+    pub target: &'a str,
+    pub sources: &'a [String],
+    pub memory_limit: Option<u64>,
+}
+
+/// A collection of [`Language`]s, indexed by name and alias.
+#[derive(Debug)]
+pub(crate) struct LanguageRegistry {
+    languages: BTreeMap<String, Language>,
+    names: BTreeMap<String, String>,
+}
+
+impl LanguageRegistry {
+    /// Creates the registry that ships with omegajail.
+    pub(crate) fn builtin() -> Result<LanguageRegistry> {
+        let mut registry = LanguageRegistry {
+            languages: BTreeMap::new(),
+            names: BTreeMap::new(),
+        };
+        registry
+            .extend(DEFAULT_REGISTRY_CONTENTS)
+            .context("parse builtin language registry")?;
+        Ok(registry)
+    }
+
+    /// Creates the registry that ships with omegajail, extended with the definitions in the
+    /// `languages.toml` file in `root`, if present.
+    pub(crate) fn load<P: AsRef<Path>>(root: P) -> Result<LanguageRegistry> {
+        let mut registry = LanguageRegistry::builtin()?;
+        let registry_path = root.as_ref().join(REGISTRY_FILENAME);
+        if registry_path.exists() {
+            let contents = read_to_string(&registry_path)
+                .with_context(|| anyhow!("read {:?}", &registry_path))?;
+            registry
+                .extend(&contents)
+                .with_context(|| anyhow!("parse {:?}", &registry_path))?;
+        }
+        Ok(registry)
+    }
+
+    /// Adds the languages defined in `contents`, replacing any existing definitions (and their
+    /// aliases) with the same name.
+    fn extend(&mut self, contents: &str) -> Result<()> {
+        let registry_file: RegistryFile = toml::from_str(contents)?;
+        for (name, language) in registry_file.languages {
+            if let Some(previous) = self.languages.remove(&name) {
+                for alias in &previous.aliases {
+                    self.names.remove(alias);
+                }
+            }
+            for recipe in language.compile.iter().chain(language.run.iter()) {
+                for mount in &recipe.mounts {
+                    if !mount.target.is_absolute() {
+                        bail!(
+                            "language {}: mount target {:?} is not absolute",
+                            name,
+                            &mount.target
+                        );
+                    }
+                }
+            }
+            for alias in language.aliases.iter().chain([&name]) {
+                if let Some(existing) = self.names.get(alias) {
+                    if existing != &name {
+                        bail!(
+                            "language {}: name {:?} is already used by language {}",
+                            name,
+                            alias,
+                            existing
+                        );
+                    }
+                }
+                self.names.insert(alias.clone(), name.clone());
+            }
+            self.languages.insert(name, language);
+        }
+        Ok(())
+    }
+
+    /// Looks up a language by its name or one of its aliases.
+    pub(crate) fn resolve(&self, name: &str) -> Result<&Language> {
+        self.names
+            .get(name)
+            .and_then(|canonical_name| self.languages.get(canonical_name))
+            .ok_or_else(|| {
+                anyhow!(
+                    "unknown language {:?}, expected one of: {}",
+                    name,
+                    self.names.keys().map(|s| s.as_str()).collect::<Vec<_>>().join(", ")
+                )
+            })
+    }
+}
+
+impl Recipe {
+    /// Returns the arguments of the process, with all the placeholders expanded.
+    pub(crate) fn expand_args(&self, ctx: &RecipeContext) -> Result<Vec<String>> {
+        let mut args = Vec::<String>::new();
+        for arg in &self.args {
+            match arg.as_str() {
+                "{sources}" => match &self.source_language_flag {
+                    Some(lang_flag) => add_sources(&mut args, lang_flag, ctx.sources),
+                    None => args.extend(ctx.sources.iter().cloned()),
+                },
+                "{memory-limit-args}" => {
+                    if ctx.memory_limit.is_some() {
+                        for memory_limit_arg in &self.memory_limit_args {
+                            args.push(self.expand_placeholders(memory_limit_arg, ctx)?);
+                        }
+                    }
+                }
+                _ => args.push(self.expand_placeholders(arg, ctx)?),
+            }
+        }
+        Ok(args)
+    }
+
+    /// Returns the memory limit that the sandbox should enforce, accounting for the runtime's
+    /// overhead.
+    pub(crate) fn memory_limit(&self, memory_limit: Option<u64>) -> Option<u64> {
+        if !self.enforce_memory_limit {
+            return None;
+        }
+        match memory_limit.map(|m| m.saturating_add(self.extra_memory_size_in_bytes)) {
+            Some(u64::MAX) => None,
+            m => m,
+        }
+    }
+
+    /// Returns the memory limit that the memory cgroup should enforce. Unlike `RLIMIT_AS`, the
+    /// cgroup only counts resident memory, so the runtime's reserved address space is not added.
+    pub(crate) fn cgroup_memory_limit(&self, memory_limit: Option<u64>) -> Option<u64> {
+        if !self.enforce_memory_limit {
+            return None;
+        }
+        memory_limit.map(|m| m.saturating_add(self.cgroup_extra_memory_size_in_bytes))
+    }
+
+    fn expand_placeholders(&self, arg: &str, ctx: &RecipeContext) -> Result<String> {
+        let mut result = String::new();
+        let mut rest = arg;
+        while let Some(start) = rest.find('{') {
+            result.push_str(&rest[..start]);
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| anyhow!("unterminated placeholder in {:?}", arg))?
+                + start;
+            match &rest[start + 1..end] {
+                "target" => result.push_str(ctx.target),
+                "source-dir" => {
+                    let source = ctx
+                        .sources
+                        .first()
+                        .ok_or_else(|| anyhow!("empty --compile-source"))?;
+                    let source_dir = Path::new(source)
+                        .parent()
+                        .context("invalid --compile-source")?;
+                    if source_dir != Path::new("") {
+                        result.push_str(
+                            source_dir
+                                .to_str()
+                                .ok_or_else(|| anyhow!("could not convert path to string"))?,
+                        );
+                        result.push('/');
+                    }
+                }
+                "memory-limit" => {
+                    let memory_limit = ctx
+                        .memory_limit
+                        .ok_or_else(|| anyhow!("{:?} requires a memory limit", arg))?;
+                    result.push_str(
+                        &(memory_limit.saturating_add(self.min_heap_size_in_bytes)).to_string(),
+                    );
+                }
+                placeholder => bail!("unknown placeholder {{{}}} in {:?}", placeholder, arg),
+            }
+            rest = &rest[end + 1..];
+        }
+        result.push_str(rest);
+        Ok(result)
+    }
+}
+
+fn add_sources(execve_args: &mut Vec<String>, lang_flag: &str, compile_sources: &[String]) {
+    let mut needs_flag = true;
+    for s in compile_sources.iter() {
+        if s.ends_with(".S") {
+            execve_args.extend([String::from("-xassembler-with-cpp"), s.clone()]);
+            needs_flag = true;
+        } else if s.ends_with(".s") {
+            execve_args.extend([String::from("-xassembler"), s.clone()]);
+            needs_flag = true;
+        } else {
+            if needs_flag {
+                execve_args.push(String::from(lang_flag));
+                needs_flag = false;
+            }
+            execve_args.push(s.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::languages::{LanguageRegistry, RecipeContext};
+
+    fn sources(sources: &[&str]) -> Vec<String> {
+        sources.iter().map(|s| String::from(*s)).collect()
+    }
+
+    #[test]
+    fn test_builtin_aliases() -> Result<()> {
+        let registry = LanguageRegistry::builtin()?;
+        for name in [
+            "c", "c11-gcc", "c11-clang", "cpp", "cpp03-gcc", "cpp03-clang", "cpp11", "cpp11-gcc",
+            "cpp11-clang", "cpp17-gcc", "cpp17-clang", "cpp20-gcc", "cpp20-clang", "pas", "lua",
+            "hs", "java", "kt", "py", "py2", "py3", "rb", "cs", "rs", "go", "js", "kj", "kp",
+        ] {
+            let language = registry.resolve(name)?;
+            assert!(language.compile.is_some(), "{} has no compile recipe", name);
+            assert!(language.run.is_some(), "{} has no run recipe", name);
+        }
+        assert_eq!(registry.resolve("cpp")?, registry.resolve("cpp11-gcc")?);
+        assert!(registry.resolve("brainfuck").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compile_sources() -> Result<()> {
+        let registry = LanguageRegistry::builtin()?;
+        let recipe = registry.resolve("cpp17-gcc")?.compile.as_ref().unwrap();
+        assert_eq!(
+            recipe.expand_args(&RecipeContext {
+                target: "Main",
+                sources: &sources(&["a.cpp", "b.S", "c.cpp"]),
+                memory_limit: None,
+            })?,
+            sources(&[
+                "/usr/bin/g++-10",
+                "-o",
+                "Main",
+                "-std=c++17",
+                "-O2",
+                "-xc++",
+                "a.cpp",
+                "-xassembler-with-cpp",
+                "b.S",
+                "-xc++",
+                "c.cpp",
+                "-lm",
+            ])
+        );
+
+        let recipe = registry.resolve("cs")?.compile.as_ref().unwrap();
+        assert!(recipe
+            .expand_args(&RecipeContext {
+                target: "Main",
+                sources: &sources(&["src/Main.cs"]),
+                memory_limit: None,
+            })?
+            .contains(&String::from("-out:src/Main.dll")));
+        assert!(recipe
+            .expand_args(&RecipeContext {
+                target: "Main",
+                sources: &sources(&["Main.cs"]),
+                memory_limit: None,
+            })?
+            .contains(&String::from("-out:Main.dll")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_limit() -> Result<()> {
+        let registry = LanguageRegistry::builtin()?;
+        let recipe = registry.resolve("java")?.run.as_ref().unwrap();
+        assert_eq!(recipe.memory_limit(Some(256 * 1024 * 1024)), None);
+        assert_eq!(
+            recipe.expand_args(&RecipeContext {
+                target: "Main",
+                sources: &[],
+                memory_limit: Some(256 * 1024 * 1024),
+            })?[4],
+            format!("-Xmx{}", 274 * 1024 * 1024)
+        );
+        assert_eq!(
+            recipe
+                .expand_args(&RecipeContext {
+                    target: "Main",
+                    sources: &[],
+                    memory_limit: None,
+                })?
+                .len(),
+            6
+        );
+
+        let recipe = registry.resolve("c")?.run.as_ref().unwrap();
+        assert_eq!(
+            recipe.memory_limit(Some(256 * 1024 * 1024)),
+            Some(272 * 1024 * 1024)
+        );
+        assert_eq!(recipe.memory_limit(None), None);
+
+        // The memory cgroup does not count the address space that runtimes reserve.
+        let recipe = registry.resolve("go")?.run.as_ref().unwrap();
+        assert_eq!(
+            recipe.memory_limit(Some(256 * 1024 * 1024)),
+            Some(768 * 1024 * 1024)
+        );
+        assert_eq!(
+            recipe.cgroup_memory_limit(Some(256 * 1024 * 1024)),
+            Some(256 * 1024 * 1024)
+        );
+        let recipe = registry.resolve("cs")?.run.as_ref().unwrap();
+        assert_eq!(
+            recipe.cgroup_memory_limit(Some(256 * 1024 * 1024)),
+            Some(276 * 1024 * 1024)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_extend() -> Result<()> {
+        let mut registry = LanguageRegistry::builtin()?;
+        registry.extend(
+            r#"
+            [languages.cpp23-gcc.run]
+            seccomp-profile = "cpp"
+            args = ["./{target}"]
+
+            [languages.c.run]
+            seccomp-profile = "cpp-asan"
+            args = ["./{target}"]
+            "#,
+        )?;
+        assert!(registry.resolve("cpp23-gcc")?.compile.is_none());
+        assert_eq!(
+            registry.resolve("c")?.run.as_ref().unwrap().seccomp_profile,
+            "cpp-asan"
+        );
+        // Replacing a language also drops its aliases.
+        assert!(registry.resolve("c11-gcc").is_err());
+
+        // "cpp" is already an alias of cpp11-gcc.
+        assert!(LanguageRegistry::builtin()?
+            .extend(
+                r#"
+                [languages.cpp23-gcc]
+                aliases = ["cpp"]
+                "#,
+            )
+            .is_err());
+
+        Ok(())
+    }
+}
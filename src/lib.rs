@@ -3,10 +3,10 @@
 
 mod args;
 pub mod jail;
+mod languages;
+pub mod seccomp;
 #[doc(hidden)]
 pub mod sys;
 
-pub use args::Args;
-pub use jail::Command;
-
-pub mod attack_bert;
+pub use args::{Args, MetaFormat, PolicyCommand, Subcommands};
+pub use jail::{BatchCommand, Command, InteractiveCommand};
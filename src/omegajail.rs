@@ -1,13 +1,131 @@
+use std::collections::HashSet;
 use std::fs::File;
 use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use anyhow::{bail, Context, Result};
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches, Subcommand};
 use nix::unistd::dup2;
 
+fn run_policy_command(command: omegajail::PolicyCommand) -> Result<()> {
+    match command {
+        omegajail::PolicyCommand::Compile {
+            default_action,
+            policy,
+            output,
+        } => {
+            let default_action = default_action
+                .map(|action| action.parse::<omegajail::seccomp::Action>())
+                .transpose()?;
+            let program = omegajail::seccomp::compile_file(Path::new(&policy), default_action)?;
+            std::fs::write(&output, program).with_context(|| format!("write {}", &output))?;
+        }
+        omegajail::PolicyCommand::Check { paths } => {
+            let mut policies = Vec::new();
+            for path in paths.iter().map(PathBuf::from) {
+                if !path.is_dir() {
+                    policies.push(path);
+                    continue;
+                }
+                let mut entries = std::fs::read_dir(&path)
+                    .with_context(|| format!("read {:?}", &path))?
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect::<std::io::Result<Vec<_>>>()
+                    .with_context(|| format!("read {:?}", &path))?;
+                entries.retain(|entry| entry.extension().is_some_and(|ext| ext == "policy"));
+                entries.sort();
+                policies.extend(entries);
+            }
+
+            // Files that are included by several policies are only reported once.
+            let mut reported = HashSet::new();
+            for policy in &policies {
+                for diagnostic in omegajail::seccomp::check_file(policy)? {
+                    if reported.insert(diagnostic.clone()) {
+                        println!("{}", diagnostic);
+                    }
+                }
+            }
+            if !reported.is_empty() {
+                bail!(
+                    "found {} problems in {} policies",
+                    reported.len(),
+                    policies.len()
+                );
+            }
+        }
+        omegajail::PolicyCommand::Dump {
+            program,
+            syscall,
+            arg,
+        } => {
+            let arch = omegajail::seccomp::Arch::native();
+            let program = omegajail::seccomp::bpf::from_bytes(
+                &std::fs::read(&program).with_context(|| format!("read {}", &program))?,
+            )?;
+            match syscall {
+                None => print!("{}", omegajail::seccomp::disassemble(&arch, &program)),
+                Some(syscall) => {
+                    let value = omegajail::seccomp::evaluate(&arch, &program, &syscall, &arg)?;
+                    match omegajail::seccomp::Action::from_ret_value(value) {
+                        Some(action) => println!("{}({}): {}", syscall, arg.join(", "), action),
+                        None => println!("{}({}): {:#x}", syscall, arg.join(", "), value),
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
 #[doc(hidden)]
 fn main() -> Result<()> {
-    let args = omegajail::Args::parse();
+    let matches = omegajail::Subcommands::augment_subcommands(
+        omegajail::Args::command()
+            .subcommand_negates_reqs(true)
+            .args_conflicts_with_subcommands(true),
+    )
+    .get_matches();
+    if matches.subcommand().is_some() {
+        env_logger::Builder::new()
+            .filter(None, log::LevelFilter::Info)
+            .init();
+        match omegajail::Subcommands::from_arg_matches(&matches).unwrap_or_else(|err| err.exit()) {
+            omegajail::Subcommands::Serve {
+                socket,
+                socket_mode,
+                root,
+                homedir_root,
+                cgroup_path,
+                bind,
+                allow_sigsys_fallback,
+            } => {
+                return omegajail::jail::serve(
+                    &socket,
+                    socket_mode,
+                    omegajail::jail::ServeConfig {
+                        root,
+                        homedir_root,
+                        cgroup_path,
+                        bind,
+                        allow_sigsys_fallback,
+                    },
+                )
+            }
+            omegajail::Subcommands::GcCgroups {
+                cgroup_path,
+                max_age,
+            } => {
+                let removed =
+                    omegajail::jail::gc_cgroups(&cgroup_path, Duration::from_secs(max_age))?;
+                log::info!("removed {} stale cgroups", removed);
+                return Ok(());
+            }
+            omegajail::Subcommands::Policy { command } => return run_policy_command(command),
+        }
+    }
+    let args = omegajail::Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
 
     // Redirect all logging to the stderr file.
     if let Some(stderr) = &args.stderr {
@@ -29,6 +147,35 @@ fn main() -> Result<()> {
         .filter(None, log::LevelFilter::Info)
         .init();
 
+    if let Some(batch) = &args.batch {
+        let cases = omegajail::jail::BatchCase::load_manifest(batch)?;
+        let results = omegajail::BatchCommand::new(args, cases).run()?;
+        let failed = results
+            .iter()
+            .filter(|result| !matches!(result.status, omegajail::sys::WaitStatus::Exited(_, 0)))
+            .count();
+        if failed != 0 {
+            bail!("{} of {} jails did not exit cleanly", failed, results.len());
+        }
+        return Ok(());
+    }
+
+    if let Some(interactor_args) = args.interactor_args() {
+        let result = omegajail::InteractiveCommand::new(interactor_args, args)
+            .spawn()?
+            .wait()?;
+        match (&result.interactor.status, &result.contestant.status) {
+            (
+                omegajail::sys::WaitStatus::Exited(_, 0),
+                omegajail::sys::WaitStatus::Exited(_, 0),
+            ) => {}
+            _ => {
+                bail!("jail did not exit cleanly: {:?}", result);
+            }
+        };
+        return Ok(());
+    }
+
     let result = omegajail::Command::new(args).spawn()?.wait()?;
     match result.status {
         omegajail::sys::WaitStatus::Exited(_, 0) => {}
@@ -0,0 +1,311 @@
+//! Classic BPF instructions and seccomp actions.
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+pub const BPF_LD: u16 = 0x00;
+pub const BPF_LDX: u16 = 0x01;
+pub const BPF_ST: u16 = 0x02;
+pub const BPF_STX: u16 = 0x03;
+pub const BPF_ALU: u16 = 0x04;
+pub const BPF_JMP: u16 = 0x05;
+pub const BPF_RET: u16 = 0x06;
+pub const BPF_MISC: u16 = 0x07;
+
+pub const BPF_W: u16 = 0x00;
+pub const BPF_H: u16 = 0x08;
+pub const BPF_B: u16 = 0x10;
+
+pub const BPF_IMM: u16 = 0x00;
+pub const BPF_ABS: u16 = 0x20;
+pub const BPF_IND: u16 = 0x40;
+pub const BPF_MEM: u16 = 0x60;
+pub const BPF_LEN: u16 = 0x80;
+
+pub const BPF_ADD: u16 = 0x00;
+pub const BPF_SUB: u16 = 0x10;
+pub const BPF_MUL: u16 = 0x20;
+pub const BPF_DIV: u16 = 0x30;
+pub const BPF_OR: u16 = 0x40;
+pub const BPF_AND: u16 = 0x50;
+pub const BPF_LSH: u16 = 0x60;
+pub const BPF_RSH: u16 = 0x70;
+pub const BPF_NEG: u16 = 0x80;
+pub const BPF_MOD: u16 = 0x90;
+pub const BPF_XOR: u16 = 0xa0;
+
+pub const BPF_JA: u16 = 0x00;
+pub const BPF_JEQ: u16 = 0x10;
+pub const BPF_JGT: u16 = 0x20;
+pub const BPF_JGE: u16 = 0x30;
+pub const BPF_JSET: u16 = 0x40;
+
+pub const BPF_K: u16 = 0x00;
+pub const BPF_X: u16 = 0x08;
+pub const BPF_A: u16 = 0x10;
+
+pub const BPF_TAX: u16 = 0x00;
+pub const BPF_TXA: u16 = 0x80;
+
+/// The number of scratch memory slots available to a BPF program.
+const BPF_MEMWORDS: usize = 16;
+
+pub const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+pub const SECCOMP_RET_KILL_THREAD: u32 = 0x0000_0000;
+pub const SECCOMP_RET_TRAP: u32 = 0x0003_0000;
+pub const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+pub const SECCOMP_RET_USER_NOTIF: u32 = 0x7fc0_0000;
+pub const SECCOMP_RET_TRACE: u32 = 0x7ff0_0000;
+pub const SECCOMP_RET_LOG: u32 = 0x7ffc_0000;
+pub const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+
+const SECCOMP_RET_ACTION_FULL: u32 = 0xffff_0000;
+const SECCOMP_RET_DATA: u32 = 0x0000_ffff;
+
+/// A single classic BPF instruction, laid out like the kernel's `struct sock_filter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct SockFilter {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
+impl SockFilter {
+    /// Constructs an instruction that does not jump.
+    pub fn stmt(code: u16, k: u32) -> SockFilter {
+        SockFilter {
+            code,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    /// Constructs a conditional jump instruction.
+    pub fn jump(code: u16, k: u32, jt: u8, jf: u8) -> SockFilter {
+        SockFilter { code, jt, jf, k }
+    }
+}
+
+/// Serializes `program` in the format that `seccomp(2)` expects.
+pub fn to_bytes(program: &[SockFilter]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(program.len() * 8);
+    for insn in program {
+        bytes.extend_from_slice(&insn.code.to_ne_bytes());
+        bytes.push(insn.jt);
+        bytes.push(insn.jf);
+        bytes.extend_from_slice(&insn.k.to_ne_bytes());
+    }
+    bytes
+}
+
+/// Deserializes a program that was serialized with [`to_bytes`].
+pub fn from_bytes(bytes: &[u8]) -> Result<Vec<SockFilter>> {
+    if !bytes.len().is_multiple_of(8) {
+        bail!("program size {} is not a multiple of 8", bytes.len());
+    }
+    Ok(bytes
+        .chunks_exact(8)
+        .map(|chunk| SockFilter {
+            code: u16::from_ne_bytes(chunk[0..2].try_into().unwrap()),
+            jt: chunk[2],
+            jf: chunk[3],
+            k: u32::from_ne_bytes(chunk[4..8].try_into().unwrap()),
+        })
+        .collect())
+}
+
+/// What the kernel does with a syscall, as returned by a seccomp filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Allow,
+    KillProcess,
+    KillThread,
+    Trap,
+    Trace,
+    Log,
+    UserNotify,
+    ReturnErrno(u16),
+}
+
+impl Action {
+    /// Returns the value that a seccomp filter returns for this action.
+    pub fn ret_value(&self) -> u32 {
+        match self {
+            Action::Allow => SECCOMP_RET_ALLOW,
+            Action::KillProcess => SECCOMP_RET_KILL_PROCESS,
+            Action::KillThread => SECCOMP_RET_KILL_THREAD,
+            Action::Trap => SECCOMP_RET_TRAP,
+            Action::Trace => SECCOMP_RET_TRACE,
+            Action::Log => SECCOMP_RET_LOG,
+            Action::UserNotify => SECCOMP_RET_USER_NOTIF,
+            Action::ReturnErrno(errno) => SECCOMP_RET_ERRNO | *errno as u32,
+        }
+    }
+
+    /// Returns the action for a value returned by a seccomp filter, if it is a known one.
+    pub fn from_ret_value(value: u32) -> Option<Action> {
+        Some(match value & SECCOMP_RET_ACTION_FULL {
+            SECCOMP_RET_ALLOW => Action::Allow,
+            SECCOMP_RET_KILL_PROCESS => Action::KillProcess,
+            SECCOMP_RET_KILL_THREAD => Action::KillThread,
+            SECCOMP_RET_TRAP => Action::Trap,
+            SECCOMP_RET_TRACE => Action::Trace,
+            SECCOMP_RET_LOG => Action::Log,
+            SECCOMP_RET_USER_NOTIF => Action::UserNotify,
+            SECCOMP_RET_ERRNO => Action::ReturnErrno((value & SECCOMP_RET_DATA) as u16),
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::Allow => f.write_str("allow"),
+            Action::KillProcess => f.write_str("kill-process"),
+            Action::KillThread => f.write_str("kill-thread"),
+            Action::Trap => f.write_str("trap"),
+            Action::Trace => f.write_str("trace"),
+            Action::Log => f.write_str("log"),
+            Action::UserNotify => f.write_str("user-notify"),
+            Action::ReturnErrno(errno) => write!(f, "return {}", errno),
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "allow" => Action::Allow,
+            "kill" | "kill-process" => Action::KillProcess,
+            "kill-thread" => Action::KillThread,
+            "trap" => Action::Trap,
+            "trace" => Action::Trace,
+            "log" => Action::Log,
+            "user-notify" => Action::UserNotify,
+            _ => match s.strip_prefix("return ") {
+                Some(errno) => Action::ReturnErrno(
+                    errno
+                        .trim()
+                        .parse()
+                        .with_context(|| anyhow!("invalid errno {:?}", errno))?,
+                ),
+                None => bail!("unknown action {:?}", s),
+            },
+        })
+    }
+}
+
+/// The input of a seccomp filter, laid out like the kernel's `struct seccomp_data`.
+#[derive(Debug, Clone, Default)]
+pub struct SeccompData {
+    pub nr: i32,
+    pub arch: u32,
+    pub instruction_pointer: u64,
+    pub args: [u64; 6],
+}
+
+impl SeccompData {
+    /// Reads the 32-bit word at `offset`, the way a `BPF_LD|BPF_W|BPF_ABS` instruction does.
+    fn load(&self, offset: u32) -> Result<u32> {
+        Ok(match offset {
+            0 => self.nr as u32,
+            4 => self.arch,
+            8 | 12 => (self.instruction_pointer >> ((offset - 8) * 8)) as u32,
+            16..=63 if offset.is_multiple_of(4) => {
+                let arg = self.args[(offset as usize - 16) / 8];
+                (arg >> ((offset % 8) * 8)) as u32
+            }
+            _ => bail!("invalid seccomp_data offset {}", offset),
+        })
+    }
+}
+
+/// Runs `program` against `data`, the way the kernel does, and returns the value it returns.
+/// Only the instructions that the kernel allows in seccomp filters are supported.
+pub fn simulate(program: &[SockFilter], data: &SeccompData) -> Result<u32> {
+    let mut a: u32 = 0;
+    let mut x: u32 = 0;
+    let mut mem = [0u32; BPF_MEMWORDS];
+    let mut pc = 0;
+    while let Some(insn) = program.get(pc) {
+        pc += 1;
+        match insn.code & 0x07 {
+            BPF_LD | BPF_LDX => {
+                let value = match insn.code & 0xe0 {
+                    BPF_ABS if insn.code & 0x18 == BPF_W => data.load(insn.k)?,
+                    BPF_IMM => insn.k,
+                    BPF_MEM => *mem
+                        .get(insn.k as usize)
+                        .ok_or_else(|| anyhow!("invalid memory slot {}", insn.k))?,
+                    BPF_LEN => std::mem::size_of::<SeccompData>() as u32,
+                    _ => bail!("{}: unsupported load {:#06x}", pc - 1, insn.code),
+                };
+                if insn.code & 0x07 == BPF_LD {
+                    a = value;
+                } else {
+                    x = value;
+                }
+            }
+            BPF_ST | BPF_STX => {
+                *mem.get_mut(insn.k as usize)
+                    .ok_or_else(|| anyhow!("invalid memory slot {}", insn.k))? =
+                    if insn.code & 0x07 == BPF_ST { a } else { x };
+            }
+            BPF_ALU => {
+                let operand = if insn.code & BPF_X != 0 { x } else { insn.k };
+                a = match insn.code & 0xf0 {
+                    BPF_ADD => a.wrapping_add(operand),
+                    BPF_SUB => a.wrapping_sub(operand),
+                    BPF_MUL => a.wrapping_mul(operand),
+                    BPF_DIV | BPF_MOD if operand == 0 => return Ok(0),
+                    BPF_DIV => a / operand,
+                    BPF_MOD => a % operand,
+                    BPF_OR => a | operand,
+                    BPF_AND => a & operand,
+                    BPF_XOR => a ^ operand,
+                    BPF_LSH => a.checked_shl(operand).unwrap_or(0),
+                    BPF_RSH => a.checked_shr(operand).unwrap_or(0),
+                    BPF_NEG => a.wrapping_neg(),
+                    _ => bail!("{}: unsupported alu {:#06x}", pc - 1, insn.code),
+                };
+            }
+            BPF_JMP => {
+                let operand = if insn.code & BPF_X != 0 { x } else { insn.k };
+                let taken = match insn.code & 0xf0 {
+                    BPF_JA => {
+                        pc += insn.k as usize;
+                        continue;
+                    }
+                    BPF_JEQ => a == operand,
+                    BPF_JGT => a > operand,
+                    BPF_JGE => a >= operand,
+                    BPF_JSET => a & operand != 0,
+                    _ => bail!("{}: unsupported jump {:#06x}", pc - 1, insn.code),
+                };
+                pc += if taken { insn.jt } else { insn.jf } as usize;
+            }
+            BPF_RET => {
+                return match insn.code & 0x18 {
+                    BPF_K => Ok(insn.k),
+                    BPF_A => Ok(a),
+                    _ => bail!("{}: unsupported return {:#06x}", pc - 1, insn.code),
+                };
+            }
+            BPF_MISC => match insn.code & 0xf8 {
+                BPF_TAX => x = a,
+                BPF_TXA => a = x,
+                _ => bail!("{}: unsupported misc {:#06x}", pc - 1, insn.code),
+            },
+            _ => unreachable!(),
+        }
+    }
+    bail!("program ran past its end")
+}
@@ -0,0 +1,132 @@
+//! A cache of compiled policies, keyed by the contents of the files they were compiled from.
+
+use std::fs::{create_dir_all, read, rename, write};
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use sha2::{Digest, Sha256};
+
+use crate::seccomp::{bpf, compile, Action, Arch, ParsedPolicy, PolicyParser};
+
+/// The version of the compiled filters. This must be incremented whenever the compiler or the
+/// parser change the filter they produce for the same policy files, since development builds do
+/// not change `CARGO_PKG_VERSION` and would otherwise keep using the filters that are already
+/// cached.
+pub(crate) const CACHE_FORMAT: u32 = 1;
+
+/// The two variants of the filter of a policy: one that notifies the supervisor of any syscalls
+/// that the policy does not allow, and one that kills the process instead.
+pub struct CompiledPolicy {
+    pub notify: Vec<u8>,
+    pub sigsys: Vec<u8>,
+}
+
+/// Compiles the policy at `path` into both filter variants, reusing them from `cache_dir` if the
+/// policy and all the files it includes have been compiled before with the same [`CACHE_FORMAT`].
+/// Failing to write to the cache is not an error.
+pub fn compile_file_cached(path: &Path, cache_dir: &Path) -> Result<CompiledPolicy> {
+    let arch = Arch::native();
+    let policy = PolicyParser::new(arch)
+        .parse_file(path)
+        .with_context(|| anyhow!("parse {:?}", path))?;
+    let key = cache_key(&arch, &policy);
+    let notify_path = cache_dir.join(format!("{}.bpf", key));
+    let sigsys_path = cache_dir.join(format!("sigsys/{}.bpf", key));
+
+    if let (Ok(notify), Ok(sigsys)) = (read(&notify_path), read(&sigsys_path)) {
+        return Ok(CompiledPolicy { notify, sigsys });
+    }
+
+    let compiled = CompiledPolicy {
+        notify: bpf::to_bytes(
+            &compile(&arch, &policy, Some(Action::UserNotify))
+                .with_context(|| anyhow!("compile {:?}", path))?,
+        ),
+        sigsys: bpf::to_bytes(
+            &compile(&arch, &policy, None).with_context(|| anyhow!("compile {:?}", path))?,
+        ),
+    };
+    if let Err(err) =
+        store(&notify_path, &compiled.notify).and_then(|()| store(&sigsys_path, &compiled.sigsys))
+    {
+        log::warn!("cache the compiled policy {:?}: {:#}", path, err);
+    }
+    Ok(compiled)
+}
+
+/// Returns the hex-encoded SHA-256 of the contents of every file in the policy, along with
+/// everything else that could change the compiled filters.
+fn cache_key(arch: &Arch, policy: &ParsedPolicy) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(env!("CARGO_PKG_VERSION"));
+    hasher.update([0]);
+    hasher.update(CACHE_FORMAT.to_le_bytes());
+    hasher.update(arch.name);
+    hasher.update([0]);
+    for (_, contents) in &policy.files {
+        hasher.update((contents.len() as u64).to_le_bytes());
+        hasher.update(contents);
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Writes `contents` to `path` through a temporary file, so that concurrent readers never see a
+/// partially-written file.
+fn store(path: &Path, contents: &[u8]) -> Result<()> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| anyhow!("{:?} has no parent", path))?;
+    create_dir_all(dir).with_context(|| anyhow!("create {:?}", dir))?;
+    let temp_path = dir.join(format!(
+        ".{}.{:016x}",
+        std::process::id(),
+        rand::random::<u64>()
+    ));
+    write(&temp_path, contents).with_context(|| anyhow!("write {:?}", temp_path))?;
+    rename(&temp_path, path).with_context(|| anyhow!("rename {:?} to {:?}", temp_path, path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read_dir, write};
+
+    use anyhow::Result;
+    use tempdir::TempDir;
+
+    use crate::seccomp::{compile_file, compile_file_cached, Action};
+
+    #[test]
+    fn test_compile_file_cached() -> Result<()> {
+        let dir = TempDir::new("policy")?;
+        let policy_path = dir.path().join("test.policy");
+        let cache_dir = dir.path().join("cache");
+        write(&policy_path, "@include ./base.policy\nread: allow\n")?;
+        write(dir.path().join("base.policy"), "exit: allow\n")?;
+
+        let compiled = compile_file_cached(&policy_path, &cache_dir)?;
+        assert_eq!(
+            compiled.notify,
+            compile_file(&policy_path, Some(Action::UserNotify))?
+        );
+        assert_eq!(compiled.sigsys, compile_file(&policy_path, None)?);
+        assert_eq!(read_dir(cache_dir.join("sigsys"))?.count(), 1);
+
+        let cached = compile_file_cached(&policy_path, &cache_dir)?;
+        assert_eq!(cached.notify, compiled.notify);
+        assert_eq!(cached.sigsys, compiled.sigsys);
+        assert_eq!(read_dir(cache_dir.join("sigsys"))?.count(), 1);
+
+        // Changing an included file results in a different cache entry.
+        write(dir.path().join("base.policy"), "exit_group: allow\n")?;
+        let recompiled = compile_file_cached(&policy_path, &cache_dir)?;
+        assert_ne!(recompiled.notify, compiled.notify);
+        assert_eq!(read_dir(cache_dir.join("sigsys"))?.count(), 2);
+
+        Ok(())
+    }
+}
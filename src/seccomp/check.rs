@@ -0,0 +1,167 @@
+//! Lints for policy files.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::seccomp::{Arch, Diagnostic, PolicyParser};
+
+/// Parses the policy at `path` and everything it includes, and returns all the problems found in
+/// them: lines that cannot be parsed (including unknown syscalls and constants), rules that
+/// duplicate or can never take effect because of an earlier rule for the same syscall in the same
+/// file, and `@frequency` entries for syscalls that the policy does not mention.
+///
+/// Rules in different files are not compared, since it is common for a policy to add a rule for a
+/// syscall after including a file that already has a more specific one for it.
+///
+/// Syscall names and constants are only checked for the architecture that omegajail was built for,
+/// since lines qualified with other architectures are skipped.
+pub fn check_file(path: &Path) -> Result<Vec<Diagnostic>> {
+    let (policy, mut diagnostics) = PolicyParser::new(Arch::native())
+        .parse_file_with_diagnostics(path)
+        .with_context(|| anyhow!("parse {:?}", path))?;
+
+    for statement in &policy.filter_statements {
+        for (i, filter) in statement.filters.iter().enumerate() {
+            let mut earlier = statement.filters[..i]
+                .iter()
+                .filter(|e| e.location.path == filter.location.path);
+            let message = if let Some(duplicate) = earlier
+                .clone()
+                .find(|e| e.expression == filter.expression && e.action == filter.action)
+            {
+                format!(
+                    "duplicate rule for {} (also in {})",
+                    statement.syscall, duplicate.location
+                )
+            } else if let Some(shadowing) =
+                earlier.find(|e| e.expression.is_none() || e.expression == filter.expression)
+            {
+                format!(
+                    "rule for {} is never used (shadowed by {})",
+                    statement.syscall, shadowing.location
+                )
+            } else {
+                continue;
+            };
+            diagnostics.push(Diagnostic {
+                location: filter.location.clone(),
+                message,
+            });
+        }
+    }
+
+    let mentioned: HashSet<u32> = policy
+        .filter_statements
+        .iter()
+        .map(|statement| statement.nr)
+        .collect();
+    for frequency in &policy.frequencies {
+        if !mentioned.contains(&frequency.nr) {
+            diagnostics.push(Diagnostic {
+                location: frequency.location.clone(),
+                message: format!(
+                    "frequency for {}, which the policy does not mention",
+                    frequency.syscall
+                ),
+            });
+        }
+    }
+
+    Ok(diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read_dir, write};
+    use std::path::Path;
+
+    use anyhow::Result;
+    use tempdir::TempDir;
+
+    use crate::seccomp::check_file;
+
+    #[test]
+    fn test_check_file() -> Result<()> {
+        let dir = TempDir::new("policy")?;
+        let policy_path = dir.path().join("test.policy");
+        write(
+            &policy_path,
+            concat!(
+                "@include ./base.policy\n",
+                "@frequency ./test.frequency\n",
+                "read: allow\n",
+                "read: arg0 == 0\n",
+                "open: arg1 & O_BOGUS\n",
+                "not_a_syscall: allow\n",
+                "write: arg0 == 1; return EPERM\n",
+                "write: arg0 == 1\n",
+                "write: arg0 == 2 || arg0 == 1\n",
+                "write: arg0 == 2 || arg0 == 1\n",
+            ),
+        )?;
+        write(
+            dir.path().join("base.policy"),
+            "@include ./missing.policy\nexit: allow\n",
+        )?;
+        write(dir.path().join("test.frequency"), "read: 10\nclose: 2\n")?;
+
+        let diagnostics: Vec<String> = check_file(&policy_path)?
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .map(|diagnostic| diagnostic.replace(&format!("{}/", dir.path().display()), ""))
+            .collect();
+        assert_eq!(diagnostics.len(), 7, "{:#?}", diagnostics);
+        for (diagnostic, expected) in diagnostics.iter().zip([
+            "./base.policy:1: ./missing.policy: ",
+            "test.policy:5: unknown constant",
+            "test.policy:6: nonexistent syscall",
+            "test.policy:4: rule for read is never used (shadowed by test.policy:3)",
+            "test.policy:8: rule for write is never used (shadowed by test.policy:7)",
+            "test.policy:10: duplicate rule for write (also in test.policy:9)",
+            "./test.frequency:2: frequency for close, which the policy does not mention",
+        ]) {
+            assert!(
+                diagnostic.starts_with(expected),
+                "{:?} does not start with {:?}",
+                diagnostic,
+                expected
+            );
+        }
+
+        Ok(())
+    }
+    #[test]
+    fn test_check_file_across_includes() -> Result<()> {
+        let dir = TempDir::new("policy")?;
+        let policy_path = dir.path().join("test.policy");
+        write(
+            &policy_path,
+            concat!(
+                "@include ./base.policy\n",
+                "rt_sigaction: return EINVAL\n",
+                "read: allow\n",
+            ),
+        )?;
+        write(
+            dir.path().join("base.policy"),
+            "rt_sigaction: arg0 != SIGSYS\nread: allow\n",
+        )?;
+        assert_eq!(check_file(&policy_path)?, vec![]);
+
+        // None of the shipped policies have any problems either.
+        let policies = Path::new(env!("CARGO_MANIFEST_DIR")).join("policies");
+        for entry in read_dir(&policies)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "policy")
+            {
+                assert_eq!(check_file(&path)?, vec![], "{:?}", path);
+            }
+        }
+
+        Ok(())
+    }
+}
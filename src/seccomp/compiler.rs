@@ -0,0 +1,542 @@
+//! Compilation of parsed policies into BPF programs.
+//!
+//! Any change to the programs produced here must increment `CACHE_FORMAT` in `cache.rs`.
+
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+
+use crate::seccomp::bpf::{
+    SockFilter, BPF_ABS, BPF_JA, BPF_JEQ, BPF_JGE, BPF_JGT, BPF_JMP, BPF_JSET, BPF_K, BPF_LD,
+    BPF_RET, BPF_W,
+};
+use crate::seccomp::{Action, Arch, ArgComparison, Filter, Operator, ParsedPolicy};
+
+/// The largest program that the kernel accepts.
+const BPF_MAXINSNS: usize = 4096;
+
+/// The offsets of the fields of `struct seccomp_data`.
+const SECCOMP_DATA_NR_OFFSET: u32 = 0;
+const SECCOMP_DATA_ARCH_OFFSET: u32 = 4;
+const SECCOMP_DATA_ARGS_OFFSET: u32 = 16;
+
+/// Ranges of at most this many syscalls are checked one by one instead of being split further.
+const LINEAR_THRESHOLD: usize = 4;
+
+/// A position in a program that is being emitted, counted from its end.
+type Label = usize;
+
+/// Emits a program backwards, so that every jump target is known by the time the jump is
+/// emitted. Classic BPF can only jump forwards, and conditional jumps can only skip up to 255
+/// instructions, so farther targets are reached through an unconditional jump.
+struct Emitter {
+    insns: Vec<SockFilter>,
+    returns: HashMap<u32, Label>,
+}
+
+impl Emitter {
+    fn new() -> Emitter {
+        Emitter {
+            insns: Vec::new(),
+            returns: HashMap::new(),
+        }
+    }
+
+    fn emit(&mut self, insn: SockFilter) -> Label {
+        self.insns.push(insn);
+        self.insns.len() - 1
+    }
+
+    /// Returns the number of instructions between the next one to be emitted and `target`.
+    fn distance(&self, target: Label) -> usize {
+        self.insns.len() - target - 1
+    }
+
+    fn ret(&mut self, action: Action) -> Label {
+        let value = action.ret_value();
+        if let Some(label) = self.returns.get(&value) {
+            // Returns are only shared while they are still close.
+            if self.distance(*label) < 128 {
+                return *label;
+            }
+        }
+        let label = self.emit(SockFilter::stmt(BPF_RET | BPF_K, value));
+        self.returns.insert(value, label);
+        label
+    }
+
+    fn load(&mut self, offset: u32) -> Label {
+        self.emit(SockFilter::stmt(BPF_LD | BPF_W | BPF_ABS, offset))
+    }
+
+    /// Returns a label from which `target` can be reached by a conditional jump. The margin
+    /// leaves room for the two trampolines that a single conditional jump might need.
+    fn near(&mut self, target: Label) -> Label {
+        if self.distance(target) <= 253 {
+            return target;
+        }
+        let distance = self.distance(target) as u32;
+        self.emit(SockFilter::stmt(BPF_JMP | BPF_JA, distance))
+    }
+
+    /// Returns a label that falls through to `target`.
+    fn goto(&mut self, target: Label) -> Label {
+        if self.distance(target) == 0 {
+            return target;
+        }
+        let distance = self.distance(target) as u32;
+        self.emit(SockFilter::stmt(BPF_JMP | BPF_JA, distance))
+    }
+
+    fn jump(&mut self, op: u16, k: u32, jt: Label, jf: Label) -> Label {
+        let jt = self.near(jt);
+        let jf = self.near(jf);
+        let insn = SockFilter::jump(
+            BPF_JMP | op | BPF_K,
+            k,
+            self.distance(jt) as u8,
+            self.distance(jf) as u8,
+        );
+        self.emit(insn)
+    }
+
+    fn finish(self) -> Vec<SockFilter> {
+        self.insns.into_iter().rev().collect()
+    }
+}
+
+/// A syscall and the label of the code that handles it.
+struct Case {
+    nr: u32,
+    frequency: u64,
+    label: Label,
+}
+
+/// Compiles `policy` into a BPF program for `arch`. Syscalls that are not in the policy, as well
+/// as those whose conditional filters all fail, get `default_action`, falling back to the
+/// policy's `@default` action and then to killing the process. Syscalls made from any other
+/// architecture kill the process.
+pub fn compile(
+    arch: &Arch,
+    policy: &ParsedPolicy,
+    default_action: Option<Action>,
+) -> Result<Vec<SockFilter>> {
+    let default_action = default_action
+        .or(policy.default_action)
+        .unwrap_or(Action::KillProcess);
+    let mut emitter = Emitter::new();
+
+    let mut cases = Vec::with_capacity(policy.filter_statements.len());
+    for statement in &policy.filter_statements {
+        let label = compile_filters(&mut emitter, arch, &statement.filters, default_action);
+        cases.push(Case {
+            nr: statement.nr,
+            frequency: statement.frequency,
+            label,
+        });
+    }
+    cases.sort_by_key(|case| case.nr);
+
+    let default_label = emitter.ret(default_action);
+    let dispatch = compile_dispatch(&mut emitter, &cases, default_label);
+    emitter.goto(dispatch);
+    let load_nr = emitter.load(SECCOMP_DATA_NR_OFFSET);
+    let kill = emitter.ret(Action::KillProcess);
+    emitter.jump(BPF_JEQ, arch.audit_arch, load_nr, kill);
+    emitter.load(SECCOMP_DATA_ARCH_OFFSET);
+
+    let program = emitter.finish();
+    if program.len() > BPF_MAXINSNS {
+        bail!(
+            "the program has {} instructions, more than {}",
+            program.len(),
+            BPF_MAXINSNS
+        );
+    }
+    Ok(program)
+}
+
+/// Compiles a binary search over `cases`, which must be sorted by syscall number. Each range is
+/// split so that both halves are called about as often, which keeps frequent syscalls close to
+/// the root. Small ranges are checked linearly, most frequent syscall first.
+fn compile_dispatch(emitter: &mut Emitter, cases: &[Case], default_label: Label) -> Label {
+    if cases.len() <= LINEAR_THRESHOLD {
+        let mut order: Vec<&Case> = cases.iter().collect();
+        order.sort_by_key(|case| std::cmp::Reverse(case.frequency));
+        let mut label = default_label;
+        for case in order.into_iter().rev() {
+            label = emitter.jump(BPF_JEQ, case.nr, case.label, label);
+        }
+        return label;
+    }
+
+    let total: u64 = cases.iter().map(|case| case.frequency).sum();
+    let mut accumulated = 0;
+    let mut split = 1;
+    for (i, case) in cases.iter().enumerate().take(cases.len() - 1).skip(1) {
+        accumulated += cases[i - 1].frequency;
+        split = i;
+        if accumulated + case.frequency / 2 >= total / 2 {
+            break;
+        }
+    }
+    let upper = compile_dispatch(emitter, &cases[split..], default_label);
+    let lower = compile_dispatch(emitter, &cases[..split], default_label);
+    emitter.jump(BPF_JGE, cases[split].nr, upper, lower)
+}
+
+/// Compiles the filters of a syscall, in order, followed by `default_action` if they are all
+/// conditional.
+fn compile_filters(
+    emitter: &mut Emitter,
+    arch: &Arch,
+    filters: &[Filter],
+    default_action: Action,
+) -> Label {
+    let mut label = match filters.last() {
+        Some(Filter {
+            expression: None,
+            action,
+            ..
+        }) => emitter.ret(*action),
+        _ => emitter.ret(default_action),
+    };
+    for filter in filters.iter().rev() {
+        let expression = match &filter.expression {
+            Some(expression) => expression,
+            None => continue,
+        };
+        let action = emitter.ret(filter.action);
+        let mut next = label;
+        for conjunction in expression.iter().rev() {
+            let mut matched = action;
+            for comparison in conjunction.iter().rev() {
+                matched = compile_comparison(emitter, arch, comparison, matched, next);
+            }
+            next = matched;
+        }
+        label = next;
+    }
+    label
+}
+
+/// Compiles a comparison of a syscall argument that jumps to `jt` if it holds and to `jf`
+/// otherwise. Arguments of 64-bit architectures are compared one 32-bit half at a time.
+fn compile_comparison(
+    emitter: &mut Emitter,
+    arch: &Arch,
+    comparison: &ArgComparison,
+    jt: Label,
+    jf: Label,
+) -> Label {
+    let offset = SECCOMP_DATA_ARGS_OFFSET + 8 * comparison.arg as u32;
+    let (lo, hi) = (comparison.value as u32, (comparison.value >> 32) as u32);
+    if arch.bits == 32 {
+        match comparison.op {
+            Operator::Eq => emitter.jump(BPF_JEQ, lo, jt, jf),
+            Operator::Ne => emitter.jump(BPF_JEQ, lo, jf, jt),
+            Operator::Gt => emitter.jump(BPF_JGT, lo, jt, jf),
+            Operator::Le => emitter.jump(BPF_JGT, lo, jf, jt),
+            Operator::Ge => emitter.jump(BPF_JGE, lo, jt, jf),
+            Operator::Lt => emitter.jump(BPF_JGE, lo, jf, jt),
+            Operator::MaskAny => emitter.jump(BPF_JSET, lo, jt, jf),
+            Operator::In => emitter.jump(BPF_JSET, !lo, jf, jt),
+        };
+        return emitter.load(offset);
+    }
+
+    let (lo_offset, hi_offset) = if cfg!(target_endian = "little") {
+        (offset, offset + 4)
+    } else {
+        (offset + 4, offset)
+    };
+    let (jt, jf) = match comparison.op {
+        Operator::Lt | Operator::Le | Operator::Ne => (jf, jt),
+        _ => (jt, jf),
+    };
+    match comparison.op {
+        Operator::Eq | Operator::Ne => {
+            emitter.jump(BPF_JEQ, lo, jt, jf);
+            let load_lo = emitter.load(lo_offset);
+            emitter.jump(BPF_JEQ, hi, load_lo, jf);
+        }
+        Operator::Gt | Operator::Le | Operator::Ge | Operator::Lt => {
+            let op = match comparison.op {
+                Operator::Gt | Operator::Le => BPF_JGT,
+                _ => BPF_JGE,
+            };
+            emitter.jump(op, lo, jt, jf);
+            let load_lo = emitter.load(lo_offset);
+            let hi_equal = emitter.jump(BPF_JEQ, hi, load_lo, jf);
+            emitter.jump(BPF_JGT, hi, jt, hi_equal);
+        }
+        Operator::MaskAny => {
+            emitter.jump(BPF_JSET, lo, jt, jf);
+            let load_lo = emitter.load(lo_offset);
+            emitter.jump(BPF_JSET, hi, jt, load_lo);
+        }
+        Operator::In => {
+            emitter.jump(BPF_JSET, !lo, jf, jt);
+            let load_lo = emitter.load(lo_offset);
+            emitter.jump(BPF_JSET, !hi, jf, load_lo);
+        }
+    }
+    emitter.load(hi_offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::ffi::OsStr;
+    use std::fs::{read, read_dir, write};
+    use std::path::{Path, PathBuf};
+
+    use anyhow::Result;
+    use tempdir::TempDir;
+
+    use crate::seccomp::bpf::{from_bytes, simulate, SeccompData, SockFilter};
+    use crate::seccomp::{
+        compile, Action, Arch, ArgComparison, FilterStatement, Operator, PolicyParser,
+    };
+
+    fn compile_str(policy: &str, default_action: Option<Action>) -> Result<Vec<SockFilter>> {
+        let dir = TempDir::new("policy")?;
+        let path = dir.path().join("test.policy");
+        write(&path, policy)?;
+        let arch = Arch::native();
+        compile(
+            &arch,
+            &PolicyParser::new(arch).parse_file(&path)?,
+            default_action,
+        )
+    }
+
+    fn run(program: &[SockFilter], syscall: &str, args: &[u64]) -> Result<Option<Action>> {
+        let arch = Arch::native();
+        let mut data = SeccompData {
+            nr: arch.syscall(syscall).unwrap() as i32,
+            arch: arch.audit_arch,
+            ..Default::default()
+        };
+        data.args[..args.len()].copy_from_slice(args);
+        Ok(Action::from_ret_value(simulate(program, &data)?))
+    }
+
+    #[test]
+    fn test_compile_policies() -> Result<()> {
+        let policies = Path::new(env!("CARGO_MANIFEST_DIR")).join("policies");
+        for entry in read_dir(&policies)? {
+            let path = entry?.path();
+            if path.extension() != Some(OsStr::new("policy")) {
+                continue;
+            }
+            let arch = Arch::native();
+            let policy = PolicyParser::new(arch).parse_file(&path)?;
+            compile(&arch, &policy, Some(Action::UserNotify))?;
+            compile(&arch, &policy, None)?;
+        }
+
+        let policy = PolicyParser::new(Arch::native()).parse_file(&policies.join("cpp.policy"))?;
+        let notify = compile(&Arch::native(), &policy, Some(Action::UserNotify))?;
+        let sigsys = compile(&Arch::native(), &policy, None)?;
+
+        assert_eq!(run(&notify, "read", &[])?, Some(Action::Allow));
+        assert_eq!(run(&notify, "mount", &[])?, Some(Action::UserNotify));
+        assert_eq!(run(&sigsys, "mount", &[])?, Some(Action::KillProcess));
+        assert_eq!(
+            run(&notify, "ioctl", &[])?,
+            Some(Action::ReturnErrno(libc::ENOTTY as u16))
+        );
+        assert_eq!(
+            run(&notify, "fcntl", &[3, libc::F_GETFD as u64])?,
+            Some(Action::Allow)
+        );
+        assert_eq!(
+            run(&notify, "fcntl", &[3, libc::F_SETLK as u64])?,
+            Some(Action::UserNotify)
+        );
+        assert_eq!(
+            run(&notify, "tgkill", &[1, 1, 6])?,
+            Some(Action::UserNotify)
+        );
+        assert_eq!(run(&notify, "tgkill", &[2, 2, 6])?, Some(Action::Allow));
+        assert_eq!(
+            run(&notify, "prlimit64", &[0, libc::RLIMIT_NOFILE as u64, 0])?,
+            Some(Action::Allow)
+        );
+        assert_eq!(
+            run(&notify, "prlimit64", &[0, libc::RLIMIT_NOFILE as u64, 1])?,
+            Some(Action::ReturnErrno(libc::EPERM as u16))
+        );
+
+        let data = SeccompData {
+            nr: Arch::native().syscall("read").unwrap() as i32,
+            arch: !Arch::native().audit_arch,
+            ..Default::default()
+        };
+        assert_eq!(
+            Action::from_ret_value(simulate(&notify, &data)?),
+            Some(Action::KillProcess)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compile_comparisons() -> Result<()> {
+        let values = [0, 1, 0x7fff_ffff, 0x1_0000_0000, 0x1_0000_0001, u64::MAX];
+        for op in [
+            Operator::Eq,
+            Operator::Ne,
+            Operator::Lt,
+            Operator::Le,
+            Operator::Gt,
+            Operator::Ge,
+            Operator::MaskAny,
+            Operator::In,
+        ] {
+            for value in values {
+                let program = compile_str(&format!("read: arg2 {} {}\n", op, value), None)?;
+                let comparison = ArgComparison { arg: 2, op, value };
+                for arg in values {
+                    let expected = if comparison.matches(arg) {
+                        Action::Allow
+                    } else {
+                        Action::KillProcess
+                    };
+                    assert_eq!(
+                        run(&program, "read", &[0, 0, arg])?,
+                        Some(expected),
+                        "arg2 = {:#x}, {:?}",
+                        arg,
+                        comparison
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compile_far_jumps() -> Result<()> {
+        let syscalls: Vec<&str> = syscalls::Sysno::iter()
+            .map(|sysno| sysno.name())
+            .take(200)
+            .collect();
+        let policy: String = syscalls
+            .iter()
+            .map(|syscall| {
+                format!(
+                    "{}: {{arg0 == 1 || arg1 == 2; return 1, return 2}}\n",
+                    syscall
+                )
+            })
+            .collect();
+        let program = compile_str(&policy, Some(Action::UserNotify))?;
+        assert!(program.len() > 256);
+        for syscall in syscalls {
+            assert_eq!(run(&program, syscall, &[1])?, Some(Action::ReturnErrno(1)));
+            assert_eq!(
+                run(&program, syscall, &[0, 2])?,
+                Some(Action::ReturnErrno(1))
+            );
+            assert_eq!(
+                run(&program, syscall, &[0, 0])?,
+                Some(Action::ReturnErrno(2))
+            );
+        }
+        assert_eq!(run(&program, "kexec_load", &[])?, Some(Action::UserNotify));
+
+        Ok(())
+    }
+    /// Returns the values that are worth passing as arguments to the syscall of `statement`: the
+    /// ones that it compares against, the ones right next to them, and their complements.
+    fn sample_args(statement: &FilterStatement) -> BTreeSet<u64> {
+        let mut values = BTreeSet::from([0, 1, 0xffff_ffff, 0x1_0000_0000, u64::MAX]);
+        for filter in &statement.filters {
+            for comparison in filter.expression.iter().flatten().flatten() {
+                let value = comparison.value;
+                values.extend([
+                    value,
+                    value.wrapping_sub(1),
+                    value.wrapping_add(1),
+                    !value,
+                    value & 0xffff_ffff,
+                    value | 0xffff_ffff_0000_0000,
+                ]);
+            }
+        }
+        values
+    }
+
+    /// Checks that every policy in `policies/` behaves the same as the filters that minijail's
+    /// `compile_seccomp_policy.py` compiled from it, which `make test` places in the directory
+    /// named by `OMEGAJAIL_MINIJAIL_POLICIES` (laid out like `out/policies`). The filters are not
+    /// laid out the same way, so they are compared by simulating them for every syscall, with each
+    /// argument set to the values that the policy compares it against and their neighbors.
+    #[test]
+    fn test_compile_policies_like_minijail() -> Result<()> {
+        let minijail_policies = match std::env::var_os("OMEGAJAIL_MINIJAIL_POLICIES") {
+            Some(dir) => PathBuf::from(dir),
+            None => {
+                eprintln!("OMEGAJAIL_MINIJAIL_POLICIES is not set, not comparing against minijail");
+                return Ok(());
+            }
+        };
+        let arch = Arch::native();
+        let policies = Path::new(env!("CARGO_MANIFEST_DIR")).join("policies");
+        for entry in read_dir(&policies)? {
+            let path = entry?.path();
+            if path.extension() != Some(OsStr::new("policy")) {
+                continue;
+            }
+            let name = path.with_extension("bpf");
+            let name = name.file_name().unwrap();
+            let policy = PolicyParser::new(arch).parse_file(&path)?;
+            for (default_action, minijail_path) in [
+                (Some(Action::UserNotify), minijail_policies.join(name)),
+                (None, minijail_policies.join("sigsys").join(name)),
+            ] {
+                let program = compile(&arch, &policy, default_action)?;
+                let minijail_program = from_bytes(&read(&minijail_path)?)?;
+                let max_nr = syscalls::Sysno::iter()
+                    .map(|sysno| sysno.id())
+                    .max()
+                    .unwrap();
+                for nr in (0..=max_nr + 1).chain([0x4000_0000, -1]) {
+                    let samples = match policy
+                        .filter_statements
+                        .iter()
+                        .find(|statement| statement.nr as i32 == nr)
+                    {
+                        Some(statement) => sample_args(statement),
+                        None => BTreeSet::from([0, u64::MAX]),
+                    };
+                    for audit_arch in [arch.audit_arch, !arch.audit_arch] {
+                        for arg in 0..6 {
+                            for &value in &samples {
+                                let mut data = SeccompData {
+                                    nr,
+                                    arch: audit_arch,
+                                    ..Default::default()
+                                };
+                                data.args[arg] = value;
+                                assert_eq!(
+                                    simulate(&program, &data)?,
+                                    simulate(&minijail_program, &data)?,
+                                    "{:?} differs from {:?} for {:?}",
+                                    path,
+                                    minijail_path,
+                                    data
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
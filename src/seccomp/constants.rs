@@ -0,0 +1,159 @@
+//! The named constants that can be used in policies.
+
+use nix::sys::signal::Signal;
+
+macro_rules! constants {
+    ($($name:ident),* $(,)?) => {
+        &[$((stringify!($name), libc::$name as u64)),*]
+    };
+}
+
+/// Constants that are not errnos or signals, which are looked up separately.
+const CONSTANTS: &[(&str, u64)] = constants!(
+    // fcntl(2)
+    F_DUPFD,
+    F_DUPFD_CLOEXEC,
+    F_GETFD,
+    F_SETFD,
+    F_GETFL,
+    F_SETFL,
+    F_GETLK,
+    F_SETLK,
+    F_SETLKW,
+    F_GETOWN,
+    F_SETOWN,
+    F_GETPIPE_SZ,
+    F_SETPIPE_SZ,
+    FD_CLOEXEC,
+    // open(2)
+    O_RDONLY,
+    O_WRONLY,
+    O_RDWR,
+    O_ACCMODE,
+    O_APPEND,
+    O_CLOEXEC,
+    O_CREAT,
+    O_DIRECTORY,
+    O_EXCL,
+    O_NOCTTY,
+    O_NOFOLLOW,
+    O_NONBLOCK,
+    O_PATH,
+    O_TRUNC,
+    // stat(2)
+    S_IFMT,
+    S_IFSOCK,
+    S_IFLNK,
+    S_IFREG,
+    S_IFBLK,
+    S_IFDIR,
+    S_IFCHR,
+    S_IFIFO,
+    // mmap(2)
+    PROT_NONE,
+    PROT_READ,
+    PROT_WRITE,
+    PROT_EXEC,
+    MAP_SHARED,
+    MAP_PRIVATE,
+    MAP_FIXED,
+    MAP_ANONYMOUS,
+    MAP_NORESERVE,
+    MAP_STACK,
+    // clone(2)
+    CLONE_VM,
+    CLONE_FS,
+    CLONE_FILES,
+    CLONE_SIGHAND,
+    CLONE_PTRACE,
+    CLONE_VFORK,
+    CLONE_PARENT,
+    CLONE_THREAD,
+    CLONE_NEWNS,
+    CLONE_SYSVSEM,
+    CLONE_SETTLS,
+    CLONE_PARENT_SETTID,
+    CLONE_CHILD_CLEARTID,
+    CLONE_DETACHED,
+    CLONE_UNTRACED,
+    CLONE_CHILD_SETTID,
+    CLONE_NEWCGROUP,
+    CLONE_NEWUTS,
+    CLONE_NEWIPC,
+    CLONE_NEWUSER,
+    CLONE_NEWPID,
+    CLONE_NEWNET,
+    CLONE_IO,
+    // getrlimit(2)
+    RLIMIT_CPU,
+    RLIMIT_FSIZE,
+    RLIMIT_DATA,
+    RLIMIT_STACK,
+    RLIMIT_CORE,
+    RLIMIT_RSS,
+    RLIMIT_NPROC,
+    RLIMIT_NOFILE,
+    RLIMIT_MEMLOCK,
+    RLIMIT_AS,
+    // prctl(2)
+    PR_SET_PDEATHSIG,
+    PR_GET_PDEATHSIG,
+    PR_GET_DUMPABLE,
+    PR_SET_DUMPABLE,
+    PR_SET_NAME,
+    PR_GET_NAME,
+    PR_SET_SECCOMP,
+    PR_SET_NO_NEW_PRIVS,
+    PR_GET_NO_NEW_PRIVS,
+    PR_SET_THP_DISABLE,
+    PR_GET_THP_DISABLE,
+    // ioctl(2)
+    TCGETS,
+    TCSETS,
+    TIOCGWINSZ,
+    TIOCSWINSZ,
+    TIOCGPGRP,
+    FIONREAD,
+    FIONBIO,
+    FIOCLEX,
+    FIONCLEX,
+    // futex(2)
+    FUTEX_WAIT,
+    FUTEX_WAKE,
+    FUTEX_PRIVATE_FLAG,
+    FUTEX_CLOCK_REALTIME,
+    // socket(2)
+    AF_UNIX,
+    AF_INET,
+    AF_INET6,
+    SOCK_STREAM,
+    SOCK_DGRAM,
+    SOCK_CLOEXEC,
+    SOCK_NONBLOCK,
+    // madvise(2)
+    MADV_NORMAL,
+    MADV_RANDOM,
+    MADV_SEQUENTIAL,
+    MADV_WILLNEED,
+    MADV_DONTNEED,
+    MADV_FREE,
+    // clock_gettime(2)
+    CLOCK_REALTIME,
+    CLOCK_MONOTONIC,
+    CLOCK_PROCESS_CPUTIME_ID,
+    CLOCK_THREAD_CPUTIME_ID,
+);
+
+/// Returns the value of the constant called `name`.
+pub(crate) fn lookup(name: &str) -> Option<u64> {
+    if let Some((_, value)) = CONSTANTS.iter().find(|(constant, _)| *constant == name) {
+        return Some(*value);
+    }
+    if let Ok(signal) = name.parse::<Signal>() {
+        return Some(signal as u64);
+    }
+    (1..4096)
+        .map(syscalls::Errno::new)
+        .find(|errno| errno.name() == Some(name))
+        .map(|errno| errno.into_raw() as u64)
+}
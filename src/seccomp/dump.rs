@@ -0,0 +1,296 @@
+//! A disassembler for compiled filters.
+
+use std::fmt::Write;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::seccomp::bpf::*;
+use crate::seccomp::parser::parse_number;
+use crate::seccomp::Arch;
+
+/// Disassembles `program` into one line per instruction. Jump targets are absolute, and
+/// instructions are annotated with the `seccomp_data` fields they load, the syscalls and
+/// architectures they compare against, and the actions they return.
+pub fn disassemble(arch: &Arch, program: &[SockFilter]) -> String {
+    // The offset in `seccomp_data` that the accumulator was loaded from before each instruction,
+    // if it is the same in every path that reaches it. Jumps only go forward, so this can be
+    // computed in a single pass.
+    let mut loaded: Vec<Option<Option<u32>>> = vec![None; program.len()];
+    if !program.is_empty() {
+        loaded[0] = Some(None);
+    }
+
+    let mut output = String::new();
+    for (pc, insn) in program.iter().enumerate() {
+        let a = loaded[pc].flatten();
+        let (mnemonic, comment) = describe(arch, pc, insn, a);
+        if comment.is_empty() {
+            writeln!(output, "{:04}: {}", pc, mnemonic).unwrap();
+        } else {
+            writeln!(output, "{:04}: {:<32} # {}", pc, mnemonic, comment).unwrap();
+        }
+
+        let next = match insn.code & 0x07 {
+            BPF_LD if insn.code & 0xf8 == BPF_ABS | BPF_W => Some(insn.k),
+            BPF_LD | BPF_ALU => None,
+            BPF_MISC if insn.code & 0xf8 == BPF_TXA => None,
+            _ => a,
+        };
+        let successors = match insn.code & 0x07 {
+            BPF_RET => vec![],
+            BPF_JMP if insn.code & 0xf0 == BPF_JA => vec![pc + 1 + insn.k as usize],
+            BPF_JMP => vec![pc + 1 + insn.jt as usize, pc + 1 + insn.jf as usize],
+            _ => vec![pc + 1],
+        };
+        for successor in successors {
+            if let Some(state) = loaded.get_mut(successor) {
+                *state = match *state {
+                    None => Some(next),
+                    Some(previous) if previous == next => Some(next),
+                    Some(_) => Some(None),
+                };
+            }
+        }
+    }
+    output
+}
+
+/// Returns the mnemonic of `insn` and a comment that explains it, given the offset in
+/// `seccomp_data` that the accumulator was loaded from, if known.
+fn describe(arch: &Arch, pc: usize, insn: &SockFilter, a: Option<u32>) -> (String, String) {
+    let operand = |insn: &SockFilter| {
+        if insn.code & BPF_X != 0 {
+            "x".to_string()
+        } else {
+            format!("#{:#x}", insn.k)
+        }
+    };
+    match insn.code & 0x07 {
+        BPF_LD | BPF_LDX => {
+            let name = match (insn.code & 0x07, insn.code & 0x18) {
+                (BPF_LD, BPF_W) => "ld",
+                (BPF_LD, BPF_H) => "ldh",
+                (BPF_LD, BPF_B) => "ldb",
+                (BPF_LDX, BPF_W) => "ldx",
+                (BPF_LDX, BPF_H) => "ldxh",
+                (BPF_LDX, BPF_B) => "ldxb",
+                _ => return unknown(insn),
+            };
+            match insn.code & 0xe0 {
+                BPF_ABS => (
+                    format!("{} [{}]", name, insn.k),
+                    field(insn.k).unwrap_or_default(),
+                ),
+                BPF_IND => (format!("{} [x + {}]", name, insn.k), String::new()),
+                BPF_IMM => (format!("{} #{:#x}", name, insn.k), String::new()),
+                BPF_MEM => (format!("{} M[{}]", name, insn.k), String::new()),
+                BPF_LEN => (format!("{} #len", name), String::new()),
+                _ => unknown(insn),
+            }
+        }
+        BPF_ST => (format!("st M[{}]", insn.k), String::new()),
+        BPF_STX => (format!("stx M[{}]", insn.k), String::new()),
+        BPF_ALU => {
+            let name = match insn.code & 0xf0 {
+                BPF_ADD => "add",
+                BPF_SUB => "sub",
+                BPF_MUL => "mul",
+                BPF_DIV => "div",
+                BPF_MOD => "mod",
+                BPF_OR => "or",
+                BPF_AND => "and",
+                BPF_XOR => "xor",
+                BPF_LSH => "lsh",
+                BPF_RSH => "rsh",
+                BPF_NEG => return ("neg".to_string(), String::new()),
+                _ => return unknown(insn),
+            };
+            (format!("{} {}", name, operand(insn)), String::new())
+        }
+        BPF_JMP => {
+            let name = match insn.code & 0xf0 {
+                BPF_JA => return (format!("ja {:04}", pc + 1 + insn.k as usize), String::new()),
+                BPF_JEQ => "jeq",
+                BPF_JGT => "jgt",
+                BPF_JGE => "jge",
+                BPF_JSET => "jset",
+                _ => return unknown(insn),
+            };
+            let comment = match a {
+                Some(0) if insn.code & BPF_X == 0 => {
+                    arch.syscall_name(insn.k).unwrap_or_default().to_string()
+                }
+                Some(4) if insn.code & BPF_X == 0 && insn.k == arch.audit_arch => {
+                    arch.name.to_string()
+                }
+                Some(offset) => field(offset).unwrap_or_default(),
+                None => String::new(),
+            };
+            (
+                format!(
+                    "{} {}, {:04}, {:04}",
+                    name,
+                    operand(insn),
+                    pc + 1 + insn.jt as usize,
+                    pc + 1 + insn.jf as usize
+                ),
+                comment,
+            )
+        }
+        BPF_RET => match insn.code & 0x18 {
+            BPF_K => (
+                format!("ret #{:#x}", insn.k),
+                Action::from_ret_value(insn.k)
+                    .map(|action| action.to_string())
+                    .unwrap_or_default(),
+            ),
+            BPF_A => ("ret a".to_string(), String::new()),
+            _ => unknown(insn),
+        },
+        BPF_MISC => match insn.code & 0xf8 {
+            BPF_TAX => ("tax".to_string(), String::new()),
+            BPF_TXA => ("txa".to_string(), String::new()),
+            _ => unknown(insn),
+        },
+        _ => unreachable!(),
+    }
+}
+
+/// Returns the raw encoding of an instruction that is not understood.
+fn unknown(insn: &SockFilter) -> (String, String) {
+    (
+        format!(
+            ".insn {:#06x}, {}, {}, {:#x}",
+            insn.code, insn.jt, insn.jf, insn.k
+        ),
+        "unknown instruction".to_string(),
+    )
+}
+
+/// Returns the name of the `seccomp_data` field at `offset`.
+fn field(offset: u32) -> Option<String> {
+    Some(match offset {
+        0 => "nr".to_string(),
+        4 => "arch".to_string(),
+        8 => "instruction_pointer".to_string(),
+        12 => "instruction_pointer (high)".to_string(),
+        16..=63 if offset.is_multiple_of(4) => {
+            let arg = (offset - 16) / 8;
+            if offset.is_multiple_of(8) {
+                format!("arg{} (low)", arg)
+            } else {
+                format!("arg{} (high)", arg)
+            }
+        }
+        _ => return None,
+    })
+}
+
+/// Runs `program` for the syscall called (or numbered) `syscall` with `args`, made from `arch`,
+/// and returns the value that it returns. Arguments are numbers or constants such as `O_RDONLY`,
+/// optionally combined with `|`, and the missing ones are zero.
+pub fn evaluate(
+    arch: &Arch,
+    program: &[SockFilter],
+    syscall: &str,
+    args: &[String],
+) -> Result<u32> {
+    let nr = match arch.syscall(syscall) {
+        Some(nr) => nr,
+        None => {
+            parse_number(syscall).map_err(|_| anyhow!("nonexistent syscall {:?}", syscall))? as u32
+        }
+    };
+    if args.len() > 6 {
+        bail!("syscalls take at most 6 arguments, got {}", args.len());
+    }
+    let mut data = SeccompData {
+        nr: nr as i32,
+        arch: arch.audit_arch,
+        ..Default::default()
+    };
+    for (value, arg) in data.args.iter_mut().zip(args) {
+        for term in arg.split('|').map(str::trim) {
+            *value |= match arch.constant(term) {
+                Some(constant) => constant,
+                None => parse_number(term).map_err(|_| anyhow!("invalid argument {:?}", term))?,
+            };
+        }
+        *value &= arch.arg_mask();
+    }
+    simulate(program, &data)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+
+    use anyhow::Result;
+    use tempdir::TempDir;
+
+    use crate::seccomp::bpf::from_bytes;
+    use crate::seccomp::dump::{disassemble, evaluate};
+    use crate::seccomp::{compile_file, Action, Arch};
+
+    #[test]
+    fn test_dump() -> Result<()> {
+        let dir = TempDir::new("policy")?;
+        let policy_path = dir.path().join("test.policy");
+        write(
+            &policy_path,
+            "read: allow\nopenat: arg2 & O_CREAT; return EPERM\nopenat: allow\n",
+        )?;
+        let arch = Arch::native();
+        let program = from_bytes(&compile_file(&policy_path, Some(Action::UserNotify))?)?;
+
+        let listing = disassemble(&arch, &program);
+        assert_eq!(listing.lines().count(), program.len());
+        assert!(listing.starts_with("0000: ld [4]"), "{}", listing);
+        let annotations: Vec<&str> = listing
+            .lines()
+            .filter_map(|line| line.split_once(" # ").map(|(_, comment)| comment))
+            .collect();
+        for expected in [
+            "arch",
+            arch.name,
+            "nr",
+            "read",
+            "openat",
+            "allow",
+            "user-notify",
+            "return 1",
+        ] {
+            assert!(
+                annotations.contains(&expected),
+                "{:?} not in {}",
+                expected,
+                listing
+            );
+        }
+
+        let action = |syscall: &str, args: &[&str]| -> Result<Option<Action>> {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            Ok(Action::from_ret_value(evaluate(
+                &arch, &program, syscall, &args,
+            )?))
+        };
+        assert_eq!(action("read", &[])?, Some(Action::Allow));
+        assert_eq!(
+            action(&arch.syscall("read").unwrap().to_string(), &[])?,
+            Some(Action::Allow)
+        );
+        assert_eq!(action("write", &[])?, Some(Action::UserNotify));
+        assert_eq!(
+            action("openat", &["-100", "0", "O_RDONLY|O_CLOEXEC"])?,
+            Some(Action::Allow)
+        );
+        assert_eq!(
+            action("openat", &["-100", "0", "O_WRONLY | O_CREAT"])?,
+            Some(Action::ReturnErrno(libc::EPERM as u16))
+        );
+        assert!(action("not_a_syscall", &[]).is_err());
+        assert!(action("read", &["O_BOGUS"]).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+//! A compiler for seccomp policies.
+//!
+//! Policies are written in the same format that minijail's `compile_seccomp_policy.py` accepts:
+//! each line maps a syscall (or a `{`-delimited group of syscalls) to an action, optionally guarded
+//! by expressions on the syscall's arguments, and `@include`/`@frequency` pull in other files. The
+//! result is a classic BPF program that can be passed to `seccomp(2)`.
+
+pub mod bpf;
+mod cache;
+mod check;
+mod compiler;
+mod constants;
+mod dump;
+mod parser;
+
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use syscalls::Sysno;
+
+pub use bpf::{Action, SockFilter};
+pub use cache::{compile_file_cached, CompiledPolicy};
+pub use check::check_file;
+pub use compiler::compile;
+pub use dump::{disassemble, evaluate};
+pub use parser::{
+    ArgComparison, Diagnostic, Filter, FilterStatement, Frequency, Location, Operator,
+    ParsedPolicy, PolicyParser,
+};
+
+/// The architecture that filters are compiled for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arch {
+    /// The name used in `[arch=...]` qualifiers.
+    pub name: &'static str,
+    /// The `AUDIT_ARCH_*` value that the kernel reports in `seccomp_data.arch`.
+    pub audit_arch: u32,
+    /// The width of the syscall arguments.
+    pub bits: u32,
+}
+
+impl Arch {
+    /// Returns the architecture that omegajail was built for.
+    pub fn native() -> Arch {
+        #[cfg(target_arch = "x86_64")]
+        return Arch {
+            name: "x86_64",
+            audit_arch: 0xc000_003e,
+            bits: 64,
+        };
+        #[cfg(target_arch = "arm")]
+        return Arch {
+            name: "armv7",
+            audit_arch: 0x4000_0028,
+            bits: 32,
+        };
+    }
+
+    /// Returns the number of the syscall called `name`.
+    pub fn syscall(&self, name: &str) -> Option<u32> {
+        name.parse::<Sysno>().ok().map(|sysno| sysno.id() as u32)
+    }
+
+    /// Returns the name of the syscall numbered `nr`.
+    pub fn syscall_name(&self, nr: u32) -> Option<&'static str> {
+        Sysno::new(nr as usize).map(|sysno| sysno.name())
+    }
+
+    /// Returns the value of the constant called `name`, such as `EPERM` or `CLONE_VM`.
+    pub fn constant(&self, name: &str) -> Option<u64> {
+        constants::lookup(name)
+    }
+
+    /// Returns the mask of the bits that fit in a syscall argument.
+    pub fn arg_mask(&self) -> u64 {
+        if self.bits == 64 {
+            u64::MAX
+        } else {
+            (1 << self.bits) - 1
+        }
+    }
+}
+
+/// Compiles the policy at `path` into the serialized BPF program that `seccomp(2)` expects.
+/// Syscalls that the policy does not mention get `default_action`, or the policy's `@default`
+/// action if `None` is given.
+pub fn compile_file(path: &Path, default_action: Option<Action>) -> Result<Vec<u8>> {
+    let arch = Arch::native();
+    let policy = PolicyParser::new(arch)
+        .parse_file(path)
+        .with_context(|| anyhow!("parse {:?}", path))?;
+    let program =
+        compile(&arch, &policy, default_action).with_context(|| anyhow!("compile {:?}", path))?;
+    Ok(bpf::to_bytes(&program))
+}
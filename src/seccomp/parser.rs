@@ -0,0 +1,885 @@
+//! A parser for seccomp policy files.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Error, Result};
+
+use crate::seccomp::{Action, Arch};
+
+/// How many levels of `@include` are followed before giving up.
+const INCLUDE_DEPTH_LIMIT: usize = 10;
+
+/// A line in a policy file.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Location {
+    pub path: PathBuf,
+    pub line: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.path.display(), self.line)
+    }
+}
+
+/// How a syscall argument is compared against a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// `&`: any of the bits of the value are set in the argument.
+    MaskAny,
+    /// `in`: all of the bits that are set in the argument are also set in the value.
+    In,
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Operator::Eq => "==",
+            Operator::Ne => "!=",
+            Operator::Lt => "<",
+            Operator::Le => "<=",
+            Operator::Gt => ">",
+            Operator::Ge => ">=",
+            Operator::MaskAny => "&",
+            Operator::In => "in",
+        })
+    }
+}
+
+/// A comparison of a syscall argument, such as `arg1 == F_GETFD`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArgComparison {
+    pub arg: usize,
+    pub op: Operator,
+    pub value: u64,
+}
+
+impl ArgComparison {
+    /// Returns whether the comparison holds for an argument with value `arg`.
+    pub fn matches(&self, arg: u64) -> bool {
+        match self.op {
+            Operator::Eq => arg == self.value,
+            Operator::Ne => arg != self.value,
+            Operator::Lt => arg < self.value,
+            Operator::Le => arg <= self.value,
+            Operator::Gt => arg > self.value,
+            Operator::Ge => arg >= self.value,
+            Operator::MaskAny => arg & self.value != 0,
+            Operator::In => arg & !self.value == 0,
+        }
+    }
+}
+
+/// An action for a syscall, optionally guarded by an expression on its arguments.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    /// The expression in disjunctive normal form: the filter applies if all the comparisons in
+    /// any of the inner lists hold. `None` if the filter applies unconditionally.
+    pub expression: Option<Vec<Vec<ArgComparison>>>,
+    pub action: Action,
+    pub location: Location,
+}
+
+/// All the filters for a single syscall, which are evaluated in order.
+#[derive(Debug, Clone)]
+pub struct FilterStatement {
+    pub syscall: String,
+    pub nr: u32,
+    /// How often the syscall is expected to be called, relative to the others.
+    pub frequency: u64,
+    pub filters: Vec<Filter>,
+}
+
+/// An entry in a `@frequency` file.
+#[derive(Debug, Clone)]
+pub struct Frequency {
+    pub syscall: String,
+    pub nr: u32,
+    pub count: u64,
+    pub location: Location,
+}
+
+/// The result of parsing a policy file and everything it includes.
+#[derive(Debug, Clone)]
+pub struct ParsedPolicy {
+    /// The action set with `@default`, if any.
+    pub default_action: Option<Action>,
+    pub filter_statements: Vec<FilterStatement>,
+    pub frequencies: Vec<Frequency>,
+    /// The paths and contents of every file that was read, in order.
+    pub files: Vec<(PathBuf, String)>,
+}
+
+/// A problem in a policy file.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Diagnostic {
+    pub location: Location,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TokenKind {
+    Default,
+    Include,
+    Frequency,
+    Path,
+    Numeric,
+    Colon,
+    Semicolon,
+    Comma,
+    Complement,
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    Or,
+    And,
+    BitwiseOr,
+    Op,
+    Equal,
+    Argument,
+    Return,
+    Action,
+    Identifier,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    value: String,
+}
+
+/// Splits a logical line of a policy file into tokens.
+fn tokenize(line: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = line.trim_start();
+    while !rest.is_empty() {
+        if rest.starts_with('#') {
+            break;
+        }
+        let word_len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '@'))
+            .unwrap_or(rest.len());
+        let (kind, len) =
+            if rest.starts_with('/') || rest.starts_with("./") || rest.starts_with("../") {
+                (
+                    TokenKind::Path,
+                    rest.find(char::is_whitespace).unwrap_or(rest.len()),
+                )
+            } else if rest.starts_with('@') {
+                (
+                    match &rest[..word_len] {
+                        "@default" => TokenKind::Default,
+                        "@include" => TokenKind::Include,
+                        "@frequency" => TokenKind::Frequency,
+                        directive => bail!("unknown directive {:?}", directive),
+                    },
+                    word_len,
+                )
+            } else if rest.starts_with(|c: char| c.is_ascii_digit())
+                || (rest.starts_with('-') && rest[1..].starts_with(|c: char| c.is_ascii_digit()))
+            {
+                (
+                    TokenKind::Numeric,
+                    rest[1..]
+                        .find(|c: char| !c.is_ascii_alphanumeric())
+                        .map_or(rest.len(), |len| len + 1),
+                )
+            } else if rest.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+                let word = &rest[..word_len];
+                (
+                    match word {
+                        "in" => TokenKind::Op,
+                        "return" => TokenKind::Return,
+                        "allow" | "kill" | "kill-process" | "kill-thread" | "trap" | "trace"
+                        | "log" | "user-notify" => TokenKind::Action,
+                        _ if word.starts_with("arg")
+                            && word.len() > 3
+                            && word[3..].chars().all(|c| c.is_ascii_digit()) =>
+                        {
+                            TokenKind::Argument
+                        }
+                        _ => TokenKind::Identifier,
+                    },
+                    word_len,
+                )
+            } else {
+                match rest.as_bytes() {
+                    [b'|', b'|', ..] => (TokenKind::Or, 2),
+                    [b'&', b'&', ..] => (TokenKind::And, 2),
+                    [b'=', b'=', ..] | [b'!', b'=', ..] | [b'<', b'=', ..] | [b'>', b'=', ..] => {
+                        (TokenKind::Op, 2)
+                    }
+                    [b'&', ..] | [b'<', ..] | [b'>', ..] => (TokenKind::Op, 1),
+                    [b'|', ..] => (TokenKind::BitwiseOr, 1),
+                    [b'=', ..] => (TokenKind::Equal, 1),
+                    [b':', ..] => (TokenKind::Colon, 1),
+                    [b';', ..] => (TokenKind::Semicolon, 1),
+                    [b',', ..] => (TokenKind::Comma, 1),
+                    [b'~', ..] => (TokenKind::Complement, 1),
+                    [b'(', ..] => (TokenKind::LParen, 1),
+                    [b')', ..] => (TokenKind::RParen, 1),
+                    [b'{', ..] => (TokenKind::LBrace, 1),
+                    [b'}', ..] => (TokenKind::RBrace, 1),
+                    [b'[', ..] => (TokenKind::LBracket, 1),
+                    [b']', ..] => (TokenKind::RBracket, 1),
+                    _ => bail!(
+                        "unexpected character {:?}",
+                        rest.chars().next().unwrap_or_default()
+                    ),
+                }
+            };
+        tokens.push(Token {
+            kind,
+            value: rest[..len].to_string(),
+        });
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// The tokens of a logical line that are yet to be parsed.
+struct Tokens {
+    tokens: std::vec::IntoIter<Token>,
+    peeked: Option<Token>,
+}
+
+impl Tokens {
+    fn new(tokens: Vec<Token>) -> Tokens {
+        let mut tokens = tokens.into_iter();
+        Tokens {
+            peeked: tokens.next(),
+            tokens,
+        }
+    }
+
+    fn peek(&self) -> Option<TokenKind> {
+        self.peeked.as_ref().map(|token| token.kind)
+    }
+
+    fn next(&mut self, what: &str) -> Result<Token> {
+        let token = self
+            .peeked
+            .take()
+            .ok_or_else(|| anyhow!("missing {}", what))?;
+        self.peeked = self.tokens.next();
+        Ok(token)
+    }
+
+    fn expect(&mut self, kind: TokenKind, what: &str) -> Result<Token> {
+        let token = self.next(what)?;
+        if token.kind != kind {
+            bail!("expected {}, got {:?}", what, token.value);
+        }
+        Ok(token)
+    }
+
+    /// Consumes the next token if it is of the given kind.
+    fn accept(&mut self, kind: TokenKind) -> bool {
+        if self.peek() != Some(kind) {
+            return false;
+        }
+        self.peeked = self.tokens.next();
+        true
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        match &self.peeked {
+            Some(token) => bail!("unexpected {:?}", token.value),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parses policy files for an [`Arch`].
+pub struct PolicyParser {
+    arch: Arch,
+    default_action: Option<Action>,
+    filter_statements: Vec<FilterStatement>,
+    statement_indices: HashMap<u32, usize>,
+    frequencies: Vec<Frequency>,
+    files: Vec<(PathBuf, String)>,
+    include_depth: usize,
+    /// Where errors are collected instead of stopping at the first one, if at all.
+    diagnostics: Option<Vec<Diagnostic>>,
+}
+
+impl PolicyParser {
+    pub fn new(arch: Arch) -> PolicyParser {
+        PolicyParser {
+            arch,
+            default_action: None,
+            filter_statements: Vec::new(),
+            statement_indices: HashMap::new(),
+            frequencies: Vec::new(),
+            files: Vec::new(),
+            include_depth: 0,
+            diagnostics: None,
+        }
+    }
+
+    /// Parses the policy at `path`, along with all the files it includes.
+    pub fn parse_file(mut self, path: &Path) -> Result<ParsedPolicy> {
+        self.parse_policy_file(path)?;
+
+        for statement in &mut self.filter_statements {
+            if let Some(first) = statement
+                .filters
+                .iter()
+                .position(|filter| filter.expression.is_none())
+            {
+                if let Some(redundant) = statement.filters.get(first + 1) {
+                    bail!(
+                        "{}: redundant unconditional action for {} (already set in {})",
+                        redundant.location,
+                        statement.syscall,
+                        statement.filters[first].location
+                    );
+                }
+            }
+        }
+
+        Ok(self.finish())
+    }
+
+    /// Parses the policy at `path` like [`PolicyParser::parse_file`] does, but instead of
+    /// stopping at the first error, skips the lines that have errors and returns them all. Only
+    /// failing to read `path` itself is an error.
+    pub fn parse_file_with_diagnostics(
+        mut self,
+        path: &Path,
+    ) -> Result<(ParsedPolicy, Vec<Diagnostic>)> {
+        self.diagnostics = Some(Vec::new());
+        self.parse_policy_file(path)?;
+        let diagnostics = self.diagnostics.take().unwrap_or_default();
+        Ok((self.finish(), diagnostics))
+    }
+
+    fn finish(mut self) -> ParsedPolicy {
+        let mut counts = HashMap::new();
+        for frequency in &self.frequencies {
+            *counts.entry(frequency.nr).or_insert(0) += frequency.count;
+        }
+        for statement in &mut self.filter_statements {
+            statement.frequency = counts.get(&statement.nr).copied().unwrap_or(1);
+        }
+        ParsedPolicy {
+            default_action: self.default_action,
+            filter_statements: self.filter_statements,
+            frequencies: self.frequencies,
+            files: self.files,
+        }
+    }
+
+    /// Records `err` if diagnostics are being collected, or returns it otherwise.
+    fn report(&mut self, location: &Location, err: Error) -> Result<()> {
+        match &mut self.diagnostics {
+            Some(diagnostics) => {
+                diagnostics.push(Diagnostic {
+                    location: location.clone(),
+                    message: format!("{:#}", err),
+                });
+                Ok(())
+            }
+            None => Err(located(location, err)),
+        }
+    }
+
+    /// Calls `f` with each logical line of the file at `path`, joining lines that end in `\`.
+    fn for_each_line(
+        &mut self,
+        path: &Path,
+        mut f: impl FnMut(&mut Self, Tokens, &Location) -> Result<()>,
+    ) -> Result<()> {
+        let contents =
+            read_to_string(path).map_err(|err| anyhow!("{}: {}", path.display(), err))?;
+        self.files.push((path.to_path_buf(), contents.clone()));
+        let mut logical_line = String::new();
+        let mut start = None;
+        for (i, line) in contents.lines().enumerate() {
+            let first_line = *start.get_or_insert(i + 1);
+            if let Some(line) = line.strip_suffix('\\') {
+                logical_line.push_str(line);
+                logical_line.push(' ');
+                continue;
+            }
+            logical_line.push_str(line);
+            let location = Location {
+                path: path.to_path_buf(),
+                line: first_line,
+            };
+            let result = tokenize(&logical_line).and_then(|tokens| {
+                if tokens.is_empty() {
+                    return Ok(());
+                }
+                f(self, Tokens::new(tokens), &location)
+            });
+            if let Err(err) = result {
+                self.report(&location, err)?;
+            }
+            logical_line.clear();
+            start = None;
+        }
+        Ok(())
+    }
+
+    fn parse_policy_file(&mut self, path: &Path) -> Result<()> {
+        self.for_each_line(path, |parser, mut tokens, location| match tokens.peek() {
+            Some(TokenKind::Include) => {
+                tokens.next("@include")?;
+                let include_path = resolve(path, &tokens.expect(TokenKind::Path, "path")?);
+                tokens.finish()?;
+                if parser.include_depth >= INCLUDE_DEPTH_LIMIT {
+                    bail!("@include nested too deeply");
+                }
+                parser.include_depth += 1;
+                let result = parser.parse_policy_file(&include_path);
+                parser.include_depth -= 1;
+                result
+            }
+            Some(TokenKind::Frequency) => {
+                tokens.next("@frequency")?;
+                let frequency_path = resolve(path, &tokens.expect(TokenKind::Path, "path")?);
+                tokens.finish()?;
+                parser.parse_frequency_file(&frequency_path)
+            }
+            Some(TokenKind::Default) => {
+                tokens.next("@default")?;
+                parser.default_action = Some(parser.parse_action(&mut tokens)?);
+                tokens.finish()
+            }
+            _ => parser.parse_filter_statement(&mut tokens, location),
+        })
+    }
+
+    fn parse_frequency_file(&mut self, path: &Path) -> Result<()> {
+        self.for_each_line(path, |parser, mut tokens, location| {
+            let syscalls = parser.parse_syscall_descriptors(&mut tokens)?;
+            tokens.expect(TokenKind::Colon, "colon")?;
+            let count = parse_number(&tokens.expect(TokenKind::Numeric, "frequency")?.value)?;
+            tokens.finish()?;
+            for (syscall, nr) in syscalls {
+                parser.frequencies.push(Frequency {
+                    syscall,
+                    nr,
+                    count,
+                    location: location.clone(),
+                });
+            }
+            Ok(())
+        })
+    }
+
+    fn parse_filter_statement(&mut self, tokens: &mut Tokens, location: &Location) -> Result<()> {
+        let syscalls = self.parse_syscall_descriptors(tokens)?;
+        tokens.expect(TokenKind::Colon, "colon")?;
+        let mut filters = Vec::new();
+        if tokens.accept(TokenKind::LBrace) {
+            loop {
+                filters.push(self.parse_single_filter(tokens, location)?);
+                if !tokens.accept(TokenKind::Comma) {
+                    break;
+                }
+            }
+            tokens.expect(TokenKind::RBrace, "closing brace")?;
+        } else {
+            filters.push(self.parse_single_filter(tokens, location)?);
+        }
+        tokens.finish()?;
+
+        for (syscall, nr) in syscalls {
+            let index = *self.statement_indices.entry(nr).or_insert_with(|| {
+                self.filter_statements.push(FilterStatement {
+                    syscall,
+                    nr,
+                    frequency: 1,
+                    filters: Vec::new(),
+                });
+                self.filter_statements.len() - 1
+            });
+            self.filter_statements[index]
+                .filters
+                .extend(filters.iter().cloned());
+        }
+        Ok(())
+    }
+
+    /// Parses either a single syscall or a `{`-delimited group of them. Syscalls that are
+    /// qualified with a different `[arch=...]` are skipped.
+    fn parse_syscall_descriptors(&self, tokens: &mut Tokens) -> Result<Vec<(String, u32)>> {
+        let mut syscalls = Vec::new();
+        if tokens.accept(TokenKind::LBrace) {
+            loop {
+                syscalls.extend(self.parse_syscall_descriptor(tokens)?);
+                if !tokens.accept(TokenKind::Comma) {
+                    break;
+                }
+            }
+            tokens.expect(TokenKind::RBrace, "closing brace")?;
+        } else {
+            syscalls.extend(self.parse_syscall_descriptor(tokens)?);
+        }
+        Ok(syscalls)
+    }
+
+    fn parse_syscall_descriptor(&self, tokens: &mut Tokens) -> Result<Option<(String, u32)>> {
+        let token = tokens.next("syscall")?;
+        // `kill` is both an action and a syscall.
+        if token.kind != TokenKind::Identifier && token.value != "kill" {
+            bail!("invalid syscall {:?}", token.value);
+        }
+        if tokens.accept(TokenKind::LBracket) {
+            let mut arch_matches = true;
+            loop {
+                let key = tokens.expect(TokenKind::Identifier, "metadata key")?;
+                tokens.expect(TokenKind::Equal, "=")?;
+                let value = tokens.expect(TokenKind::Identifier, "metadata value")?;
+                match key.value.as_str() {
+                    "arch" => arch_matches &= value.value == self.arch.name,
+                    _ => bail!("unknown metadata key {:?}", key.value),
+                }
+                if !tokens.accept(TokenKind::Comma) {
+                    break;
+                }
+            }
+            tokens.expect(TokenKind::RBracket, "closing bracket")?;
+            if !arch_matches {
+                return Ok(None);
+            }
+        }
+        let nr = self
+            .arch
+            .syscall(&token.value)
+            .ok_or_else(|| anyhow!("nonexistent syscall {:?}", token.value))?;
+        Ok(Some((token.value, nr)))
+    }
+
+    /// Parses an action, optionally preceded by an argument expression and a semicolon. Filters
+    /// with an expression and no action allow the syscall.
+    fn parse_single_filter(&self, tokens: &mut Tokens, location: &Location) -> Result<Filter> {
+        let (expression, action) = match tokens.peek() {
+            Some(TokenKind::Action) | Some(TokenKind::Return) | Some(TokenKind::Numeric) => {
+                (None, self.parse_action(tokens)?)
+            }
+            _ => {
+                let expression = self.parse_argument_expression(tokens)?;
+                let action = if tokens.accept(TokenKind::Semicolon) {
+                    self.parse_action(tokens)?
+                } else {
+                    Action::Allow
+                };
+                (Some(expression), action)
+            }
+        };
+        Ok(Filter {
+            expression,
+            action,
+            location: location.clone(),
+        })
+    }
+
+    fn parse_action(&self, tokens: &mut Tokens) -> Result<Action> {
+        let token = tokens.next("action")?;
+        match token.kind {
+            TokenKind::Action => token.value.parse(),
+            TokenKind::Return => {
+                let errno = self.parse_value(tokens)?;
+                Ok(Action::ReturnErrno(
+                    errno
+                        .try_into()
+                        .map_err(|_| anyhow!("errno {} out of range", errno))?,
+                ))
+            }
+            // Older policies used `1` to mean `allow`.
+            TokenKind::Numeric if token.value == "1" => Ok(Action::Allow),
+            _ => bail!("invalid action {:?}", token.value),
+        }
+    }
+
+    /// Parses `||`-separated lists of `&&`-separated argument comparisons.
+    fn parse_argument_expression(&self, tokens: &mut Tokens) -> Result<Vec<Vec<ArgComparison>>> {
+        let mut disjunction = Vec::new();
+        loop {
+            let mut conjunction = Vec::new();
+            loop {
+                conjunction.push(self.parse_arg_comparison(tokens)?);
+                if !tokens.accept(TokenKind::And) {
+                    break;
+                }
+            }
+            disjunction.push(conjunction);
+            if !tokens.accept(TokenKind::Or) {
+                break;
+            }
+        }
+        Ok(disjunction)
+    }
+
+    fn parse_arg_comparison(&self, tokens: &mut Tokens) -> Result<ArgComparison> {
+        let arg = tokens.expect(TokenKind::Argument, "argument")?;
+        let arg = arg.value[3..]
+            .parse()
+            .ok()
+            .filter(|arg| *arg < 6)
+            .ok_or_else(|| anyhow!("invalid argument {:?}", arg.value))?;
+        let op = match tokens.expect(TokenKind::Op, "operator")?.value.as_str() {
+            "==" => Operator::Eq,
+            "!=" => Operator::Ne,
+            "<" => Operator::Lt,
+            "<=" => Operator::Le,
+            ">" => Operator::Gt,
+            ">=" => Operator::Ge,
+            "&" => Operator::MaskAny,
+            "in" => Operator::In,
+            op => bail!("invalid operator {:?}", op),
+        };
+        Ok(ArgComparison {
+            arg,
+            op,
+            value: self.parse_value(tokens)?,
+        })
+    }
+
+    /// Parses `|`-separated constants.
+    fn parse_value(&self, tokens: &mut Tokens) -> Result<u64> {
+        let mut value = self.parse_constant(tokens)?;
+        while tokens.accept(TokenKind::BitwiseOr) {
+            value |= self.parse_constant(tokens)?;
+        }
+        Ok(value)
+    }
+
+    /// Parses a number, a named constant, or a parenthesized value, optionally complemented
+    /// with `~`.
+    fn parse_constant(&self, tokens: &mut Tokens) -> Result<u64> {
+        if tokens.accept(TokenKind::Complement) {
+            return Ok(!self.parse_constant(tokens)? & self.arch.arg_mask());
+        }
+        if tokens.accept(TokenKind::LParen) {
+            let value = self.parse_value(tokens)?;
+            tokens.expect(TokenKind::RParen, "closing parenthesis")?;
+            return Ok(value);
+        }
+        let token = tokens.next("constant")?;
+        match token.kind {
+            TokenKind::Numeric => Ok(parse_number(&token.value)? & self.arch.arg_mask()),
+            TokenKind::Identifier => self
+                .arch
+                .constant(&token.value)
+                .ok_or_else(|| anyhow!("unknown constant {:?}", token.value)),
+            _ => bail!("invalid constant {:?}", token.value),
+        }
+    }
+}
+
+/// Resolves a path in an `@include` or `@frequency` directive relative to the file it is in.
+fn resolve(path: &Path, token: &Token) -> PathBuf {
+    path.parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join(&token.value)
+}
+
+/// Parses a decimal, hexadecimal (`0x`), or octal (`0o`) number, which may be negative.
+pub(crate) fn parse_number(s: &str) -> Result<u64> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s),
+    };
+    let value = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        u64::from_str_radix(hex, 16)
+    } else if let Some(octal) = digits
+        .strip_prefix("0o")
+        .or_else(|| digits.strip_prefix("0O"))
+    {
+        u64::from_str_radix(octal, 8)
+    } else {
+        digits.parse()
+    }
+    .map_err(|_| anyhow!("invalid number {:?}", s))?;
+    Ok(if negative {
+        value.wrapping_neg()
+    } else {
+        value
+    })
+}
+
+/// Prefixes `err` with `location`, unless it already has one from an included file.
+fn located(location: &Location, err: Error) -> Error {
+    if err.is::<LocatedError>() {
+        return err;
+    }
+    LocatedError(format!("{}: {:#}", location, err)).into()
+}
+
+/// An error that has already been prefixed with the location where it happened.
+#[derive(Debug)]
+struct LocatedError(String);
+
+impl fmt::Display for LocatedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for LocatedError {}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir, write};
+
+    use anyhow::Result;
+    use tempdir::TempDir;
+
+    use crate::seccomp::{Action, Arch, ArgComparison, Operator, PolicyParser};
+
+    #[test]
+    fn test_parse_policy() -> Result<()> {
+        let dir = TempDir::new("policy")?;
+        create_dir(dir.path().join("base"))?;
+        write(
+            dir.path().join("base/base.policy"),
+            "# Exit\n{exit, exit_group}: allow\n",
+        )?;
+        write(
+            dir.path().join("test.frequency"),
+            "read: 10\n{read, write}: 5\n",
+        )?;
+        write(
+            dir.path().join("test.policy"),
+            concat!(
+                "@include ./base/base.policy\n",
+                "@frequency ./test.frequency\n",
+                "{read, fcntl64[arch=armv7], fcntl[arch=x86_64]}: allow\n",
+                "ioctl: return ENOTTY\n",
+                "write: arg0 == 1 || arg0 == 2 && \\\n",
+                "  arg2 in ~(O_RDONLY|0x1)\n",
+                "kill: {arg1 == SIGTERM; allow, arg1 & 0x10; return EPERM}\n",
+            ),
+        )?;
+        let policy =
+            PolicyParser::new(Arch::native()).parse_file(&dir.path().join("test.policy"))?;
+        let syscalls: Vec<&str> = policy
+            .filter_statements
+            .iter()
+            .map(|statement| statement.syscall.as_str())
+            .collect();
+        let fcntl = if Arch::native().name == "x86_64" {
+            "fcntl"
+        } else {
+            "fcntl64"
+        };
+        assert_eq!(
+            syscalls,
+            [
+                "exit",
+                "exit_group",
+                "read",
+                fcntl,
+                "ioctl",
+                "write",
+                "kill"
+            ]
+        );
+
+        let read = &policy.filter_statements[2];
+        assert_eq!(read.frequency, 15);
+        assert_eq!(read.filters[0].action, Action::Allow);
+        assert!(read.filters[0].expression.is_none());
+        assert_eq!(read.filters[0].location.line, 3);
+
+        let ioctl = &policy.filter_statements[4];
+        assert_eq!(ioctl.frequency, 1);
+        assert_eq!(
+            ioctl.filters[0].action,
+            Action::ReturnErrno(libc::ENOTTY as u16)
+        );
+
+        let write_statement = &policy.filter_statements[5];
+        assert_eq!(write_statement.filters[0].location.line, 5);
+        assert_eq!(
+            write_statement.filters[0].expression,
+            Some(vec![
+                vec![ArgComparison {
+                    arg: 0,
+                    op: Operator::Eq,
+                    value: 1,
+                }],
+                vec![
+                    ArgComparison {
+                        arg: 0,
+                        op: Operator::Eq,
+                        value: 2,
+                    },
+                    ArgComparison {
+                        arg: 2,
+                        op: Operator::In,
+                        value: !1 & Arch::native().arg_mask(),
+                    },
+                ],
+            ])
+        );
+
+        let kill = &policy.filter_statements[6];
+        assert_eq!(kill.filters.len(), 2);
+        assert_eq!(
+            kill.filters[1].action,
+            Action::ReturnErrno(libc::EPERM as u16)
+        );
+
+        for (contents, error) in [
+            (
+                "read: allow\nreed: allow\n",
+                "test.policy:2: nonexistent syscall \"reed\"",
+            ),
+            (
+                "read: arg0 == NOT_A_CONSTANT\n",
+                "test.policy:1: unknown constant",
+            ),
+            ("read: arg7 == 1\n", "test.policy:1: invalid argument"),
+            (
+                "read: allow\nread: allow\n",
+                "test.policy:2: redundant unconditional action",
+            ),
+            ("@include ./missing.policy\n", "test.policy:1: "),
+            (
+                "@include ./base/base.policy\nexit: allow\n",
+                "test.policy:2: redundant",
+            ),
+            ("read: allow, allow\n", "test.policy:1: unexpected \",\""),
+        ] {
+            write(dir.path().join("test.policy"), contents)?;
+            let err = PolicyParser::new(Arch::native())
+                .parse_file(&dir.path().join("test.policy"))
+                .expect_err(contents);
+            let message = format!("{:#}", err);
+            assert!(
+                message.contains(error),
+                "{:?} does not contain {:?}",
+                message,
+                error
+            );
+        }
+
+        Ok(())
+    }
+}
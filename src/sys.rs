@@ -340,11 +340,12 @@ ioctl_readwrite!(
 
 pub(crate) fn seccomp_read_notification(fd: RawFd, buf: &mut [u8]) -> Result<SeccompNotif> {
     loop {
+        // The kernel requires the buffer to be zeroed, and it is reused for every notification.
+        buf.fill(0);
         match unsafe { seccomp_notif_recv(fd, buf as *mut _ as *mut SeccompNotif) } {
             Err(Errno::EINTR) => {}
             Err(err) => {
-                log::error!("failed: {:#}", err);
-                break;
+                return Err(Error::new(err)).context("ioctl(SECCOMP_IOCTL_NOTIF_RECV)");
             }
             Ok(_) => {
                 break;
@@ -354,12 +355,76 @@ pub(crate) fn seccomp_read_notification(fd: RawFd, buf: &mut [u8]) -> Result<Sec
     Ok(unsafe { &*(buf as *mut _ as *mut SeccompNotif) }.clone())
 }
 
+#[doc(hidden)]
+#[repr(C)]
+pub struct SeccompNotifResp {
+    pub id: u64,
+    pub val: i64,
+    pub error: i32,
+    pub flags: u32,
+}
+
+ioctl_readwrite!(
+    #[doc(hidden)]
+    seccomp_notif_send,
+    SECCOMP_IOC_MAGIC,
+    1,
+    SeccompNotifResp
+);
+
+/// Lets the syscall of the notification with `id` run as if the filter had allowed it.
+pub(crate) fn seccomp_continue_notification(fd: RawFd, id: u64) -> Result<()> {
+    const SECCOMP_USER_NOTIF_FLAG_CONTINUE: u32 = 1 << 0;
+
+    let mut resp = SeccompNotifResp {
+        id,
+        val: 0,
+        error: 0,
+        flags: SECCOMP_USER_NOTIF_FLAG_CONTINUE,
+    };
+    loop {
+        match unsafe { seccomp_notif_send(fd, &mut resp) } {
+            Err(Errno::EINTR) => {}
+            Err(err) => {
+                return Err(Error::new(err)).context("ioctl(SECCOMP_IOCTL_NOTIF_SEND)");
+            }
+            Ok(_) => return Ok(()),
+        }
+    }
+}
+
 pub(crate) fn pidfd_open(pid: Pid, flags: i32) -> Result<File> {
     let fd = check_err(unsafe { libc::syscall(libc::SYS_pidfd_open, pid, flags) })?;
 
     Ok(unsafe { File::from_raw_fd(fd.try_into()?) })
 }
 
+pub(crate) fn pidfd_send_signal(pidfd: &File, signal: Signal) -> Result<()> {
+    check_err(unsafe {
+        libc::syscall(
+            libc::SYS_pidfd_send_signal,
+            pidfd.as_raw_fd(),
+            signal as i32,
+            std::ptr::null::<libc::siginfo_t>(),
+            0,
+        )
+    })
+    .with_context(|| format!("pidfd_send_signal({})", signal))?;
+
+    Ok(())
+}
+
+pub(crate) fn prlimit(pid: Pid, resource: libc::__rlimit_resource_t, limit: u64) -> Result<()> {
+    let rlimit = libc::rlimit {
+        rlim_cur: limit,
+        rlim_max: limit,
+    };
+    Errno::result(unsafe { libc::prlimit(pid.as_raw(), resource, &rlimit, std::ptr::null_mut()) })
+        .with_context(|| format!("prlimit({}, {}, {})", pid, resource, limit))?;
+
+    Ok(())
+}
+
 pub(crate) fn close_range(first: RawFd, last: Option<RawFd>, flags: u32) -> Result<()> {
     check_err(unsafe { libc::syscall(libc::SYS_close_range, first, last.unwrap_or(-1), flags) })?;
 
@@ -446,22 +511,133 @@ pub enum WaitStatus {
     ),
 }
 
+/// The reason why omegajail terminated the sandboxed process before it exited on its own.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum KilledBy {
+    /// The interactor of an interactive problem terminated first, so the contestant's program was
+    /// terminated.
+    Interactor,
+    /// The contestant's program of an interactive problem terminated abnormally, so the interactor
+    /// was terminated.
+    Contestant,
+    /// The process did not exit before the wall time limit expired. This is usually caused by the
+    /// process sleeping or being blocked rather than by it using too much CPU time.
+    WallTimeLimit,
+}
+
+impl KilledBy {
+    /// Returns the name of the reason, as written in the .meta file.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KilledBy::Interactor => "interactor",
+            KilledBy::Contestant => "contestant",
+            KilledBy::WallTimeLimit => "wall-time",
+        }
+    }
+}
+
+/// The classification of the result of a sandboxed process, taking into account the limits it was
+/// run with.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// The process exited cleanly within all its limits.
+    Ok,
+    /// The process exited with a non-zero status, or was terminated by a signal that is not
+    /// associated with any limit.
+    RuntimeError,
+    /// The process exceeded its time limit.
+    TimeLimitExceeded,
+    /// The process exceeded its memory limit.
+    MemoryLimitExceeded,
+    /// The process exceeded its output limit.
+    OutputLimitExceeded,
+    /// The process attempted to invoke a forbidden syscall.
+    RestrictedFunction,
+    /// omegajail itself failed to run or wait for the process.
+    JudgeError,
+}
+
+impl Verdict {
+    /// Returns the abbreviated name of the verdict, as written in the .meta file.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Verdict::Ok => "OK",
+            Verdict::RuntimeError => "RTE",
+            Verdict::TimeLimitExceeded => "TLE",
+            Verdict::MemoryLimitExceeded => "MLE",
+            Verdict::OutputLimitExceeded => "OLE",
+            Verdict::RestrictedFunction => "RFE",
+            Verdict::JudgeError => "JE",
+        }
+    }
+}
+
+/// Additional resource usage counters of a process, as reported by
+/// [`getrusage(2)`](https://man7.org/linux/man-pages/man2/getrusage.2.html).
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct ResourceUsage {
+    /// The number of page faults serviced without any I/O activity.
+    pub minor_faults: u64,
+    /// The number of page faults serviced that required I/O activity.
+    pub major_faults: u64,
+    /// The number of times the process voluntarily gave up the CPU before its time slice was
+    /// completed (usually to wait for a resource).
+    pub voluntary_context_switches: u64,
+    /// The number of times the process was forced to give up the CPU.
+    pub involuntary_context_switches: u64,
+    /// The number of times the filesystem had to perform input.
+    pub block_input_operations: u64,
+    /// The number of times the filesystem had to perform output.
+    pub block_output_operations: u64,
+}
+
 /// Describes the result of a process after it has terminated.
 ///
 /// This also contains information about the resource usage of the process: user time, system time,
-/// wall time, and max RSS.
+/// wall time, max RSS, and other counters like page faults and context switches.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct WaitidStatus {
     /// The exit status of the process.
     pub status: WaitStatus,
-    /// The amount of CPU time spent running userspace code.
+    /// The classification of the exit status.
+    pub verdict: Verdict,
+    /// The amount of CPU time spent running userspace code. On cgroup v2, this includes all the
+    /// processes in the sandbox.
     pub user_time: Duration,
-    /// The amount of CPU time spent running kernel code on behalf of the process.
+    /// The amount of CPU time spent running kernel code on behalf of the process. On cgroup v2,
+    /// this includes all the processes in the sandbox.
     pub system_time: Duration,
     /// The amount of wall time during which the process was running.
     pub wall_time: Duration,
     /// The maximum Resident Set Size (memory) consumed by the process.
     pub max_rss: u64,
+    /// The peak memory usage of all the processes in the sandbox, as reported by the memory
+    /// cgroup. Unlike `max_rss`, no language overhead is subtracted from this. This is only
+    /// available when the memory cgroup is in use.
+    pub cgroup_max_memory: Option<u64>,
+    /// Additional resource usage counters of the process.
+    pub rusage: ResourceUsage,
+    /// Set if omegajail terminated the process early, and why.
+    pub killed_by: Option<KilledBy>,
+    /// Whether the process tried to create more processes or threads than allowed by the pids
+    /// limit. This is only detected when the limit is enforced through a cgroup.
+    pub pids_limit_exceeded: bool,
+    /// Whether the kernel's OOM killer terminated a process in the sandbox because the memory
+    /// limit was reached. This is only detected when the limit is enforced through a cgroup.
+    pub oom_killed: bool,
+    /// The files that were requested to be extracted from the sandbox.
+    pub extracted: Vec<ExtractedFile>,
+}
+
+/// The result of copying a file out of the sandbox after the sandboxed process exited.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExtractedFile {
+    /// The path of the file, relative to the sandbox's home directory.
+    pub path: String,
+    /// The size of the file, if it was copied.
+    pub size: Option<u64>,
+    /// Why the file could not be copied, if it was not.
+    pub error: Option<String>,
 }
 
 pub(crate) fn waitid(which: WaitidWhich, options: WaitPidFlag) -> Result<WaitidStatus> {
@@ -574,12 +750,27 @@ pub(crate) fn waitid(which: WaitidWhich, options: WaitPidFlag) -> Result<WaitidS
                 bail!("unexpected si_code: {:?}", kernel_siginfo);
             }
         },
+        // The caller is responsible for classifying the status, since that depends on the limits.
+        verdict: Verdict::JudgeError,
         system_time: Duration::from_secs(rusage.ru_stime.tv_sec.try_into()?)
             + Duration::from_micros(rusage.ru_stime.tv_usec.try_into()?),
         user_time: Duration::from_secs(rusage.ru_utime.tv_sec.try_into()?)
             + Duration::from_micros(rusage.ru_utime.tv_usec.try_into()?),
         wall_time: Duration::ZERO,
         max_rss: (rusage.ru_maxrss * 1024).try_into()?,
+        cgroup_max_memory: None,
+        rusage: ResourceUsage {
+            minor_faults: rusage.ru_minflt.try_into()?,
+            major_faults: rusage.ru_majflt.try_into()?,
+            voluntary_context_switches: rusage.ru_nvcsw.try_into()?,
+            involuntary_context_switches: rusage.ru_nivcsw.try_into()?,
+            block_input_operations: rusage.ru_inblock.try_into()?,
+            block_output_operations: rusage.ru_oublock.try_into()?,
+        },
+        killed_by: None,
+        pids_limit_exceeded: false,
+        oom_killed: false,
+        extracted: vec![],
     })
 }
 
@@ -1,5 +1,5 @@
 //! A helper binary to test the various edge cases of the sandbox.
-use std::fs::read_dir;
+use std::fs::{read, read_dir};
 use std::io::{stderr, stdin, stdout, Read, Write};
 use std::process::abort;
 use std::thread::sleep;
@@ -30,6 +30,8 @@ enum Widget {
     Sigxcpu,
     /// Take a break, sleep a bit.
     Sleep,
+    /// Print the resource limits of the process.
+    Limits,
 }
 
 #[derive(Parser)]
@@ -109,6 +111,9 @@ fn main() -> Result<()> {
             // Not expected to be reached before the process is killed.
             println!("yawn");
         }
+        Widget::Limits => {
+            stdout().write_all(&read("/proc/self/limits")?)?;
+        }
     }
 
     Ok(())